@@ -0,0 +1,362 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bonito::generator::{Generator, MockGenerator};
+#[cfg(feature = "llama")]
+use bonito::hub::HubModel;
+use bonito::hub::{DEFAULT_MODEL_FILE, DEFAULT_MODEL_REPO};
+#[cfg(feature = "llama")]
+use bonito::llama::Bonito;
+use bonito::openai::{OpenAiConfig, OpenAiGenerator};
+use bonito::sampling::SamplingParams;
+use bonito::sanitize::ContextPolicy;
+use bonito::str_to_task_type;
+use bonito::task_type_to_str;
+use bonito::TaskType;
+use bonito::TASK_TYPES;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// what is done with the special tokens of the contexts, the completions are parsed with the policy
+/// they were generated with
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct ContextPolicyArgs {
+    /// What to do with the special tokens and {{context}} of a context: escape, strip or reject,
+    /// parse the completions with the policy they were generated with
+    #[arg(
+        long = "context-policy",
+        value_name = "CONTEXT_POLICY",
+        default_value = "escape"
+    )]
+    pub policy: ContextPolicy,
+}
+
+/// sampling parameters, flags override the values of `--sampling-config`
+#[derive(clap::Args, Debug, Clone)]
+pub struct SamplingArgs {
+    /// A JSON file with `SamplingParams`, e.g. {"temperature": 0.5, "seed": 42}
+    #[arg(long = "sampling-config")]
+    sampling_config: Option<PathBuf>,
+
+    /// Max number of tokens to generate after the prompt
+    #[arg(long = "max-tokens")]
+    max_tokens: Option<usize>,
+
+    /// Sampling temperature, 0 picks the most likely token
+    #[arg(long = "temperature")]
+    temperature: Option<f32>,
+
+    /// Keep only the top k most likely tokens
+    #[arg(long = "top-k")]
+    top_k: Option<i32>,
+
+    /// Nucleus sampling probability
+    #[arg(long = "top-p")]
+    top_p: Option<f32>,
+
+    /// Min-p sampling probability
+    #[arg(long = "min-p")]
+    min_p: Option<f32>,
+
+    /// Tail free sampling, 1.0 is disabled, ignored by the llama backend
+    #[arg(long = "tfs-z")]
+    tfs_z: Option<f32>,
+
+    /// Locally typical sampling, 1.0 is disabled
+    #[arg(long = "typical-p")]
+    typical_p: Option<f32>,
+
+    /// Penalty for repeated tokens, 1.0 is disabled
+    #[arg(long = "repeat-penalty")]
+    repeat_penalty: Option<f32>,
+
+    /// How many of the last tokens the repeat penalty looks at
+    #[arg(long = "repeat-last-n")]
+    repeat_last_n: Option<usize>,
+
+    /// Seed for reproducible runs
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    /// Always pick the most likely token
+    #[arg(long = "greedy")]
+    greedy: bool,
+
+    /// Stop the generation at this string, can be repeated
+    #[arg(long = "stop")]
+    stop: Vec<String>,
+
+    /// Stop after the answer line following <|pipe|>, by default true for the short answer task types
+    #[arg(long = "stop-after-answer")]
+    stop_after_answer: Option<bool>,
+
+    /// Constrain the generation with a grammar of the bonito completion shape (an instruction, one <|pipe|>
+    /// and a non-blank answer), llama backend, or an openai backend serving llama.cpp
+    #[arg(long = "constrained")]
+    constrained: bool,
+}
+
+impl SamplingArgs {
+    /// the `SamplingParams` of `--sampling-config` (or the defaults) with the flags applied
+    pub fn sampling_params(&self) -> Result<SamplingParams> {
+        let mut params = match &self.sampling_config {
+            Some(path) => {
+                let config = std::fs::read_to_string(path)
+                    .with_context(|| format!("unable to read {}", path.display()))?;
+                serde_json::from_str(&config)
+                    .with_context(|| format!("invalid sampling config {}", path.display()))?
+            }
+            None => SamplingParams::default(),
+        };
+
+        if let Some(max_tokens) = self.max_tokens {
+            params.max_tokens = max_tokens;
+        }
+        if let Some(temperature) = self.temperature {
+            params.temperature = temperature;
+        }
+        if let Some(top_k) = self.top_k {
+            params.top_k = top_k;
+        }
+        if let Some(top_p) = self.top_p {
+            params.top_p = top_p;
+        }
+        if let Some(min_p) = self.min_p {
+            params.min_p = min_p;
+        }
+        if let Some(tfs_z) = self.tfs_z {
+            params.tfs_z = tfs_z;
+        }
+        if let Some(typical_p) = self.typical_p {
+            params.typical_p = typical_p;
+        }
+        if let Some(repeat_penalty) = self.repeat_penalty {
+            params.repeat_penalty = repeat_penalty;
+        }
+        if let Some(repeat_last_n) = self.repeat_last_n {
+            params.repeat_last_n = repeat_last_n;
+        }
+        if self.seed.is_some() {
+            params.seed = self.seed;
+        }
+        if self.greedy {
+            params.greedy = true;
+        }
+        if !self.stop.is_empty() {
+            params.stop = self.stop.clone();
+        }
+        if self.stop_after_answer.is_some() {
+            params.stop_after_answer = self.stop_after_answer;
+        }
+        if self.constrained {
+            params.constrained = true;
+        }
+
+        Ok(params)
+    }
+}
+
+/// the backend generating the completions
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// llama.cpp in-process, needs the `llama` feature
+    #[cfg(feature = "llama")]
+    Llama,
+    /// an OpenAI-compatible completions endpoint at --base-url, e.g. llama-server or vLLM
+    #[value(name = "openai")]
+    OpenAi,
+    /// canned completions from --mock-responses, to test the pipeline without a model
+    Mock,
+}
+
+/// which backend generates the completions, and with which model
+#[derive(clap::Args, Debug, Clone)]
+pub struct BackendArgs {
+    /// The backend generating the completions, llama if bonitox was built with the llama feature, openai otherwise
+    #[arg(long = "backend", value_enum)]
+    backend: Option<Backend>,
+
+    /// A JSONL file of generated strings (the text after the prompt), the mock backend answers with them in turn
+    #[arg(long = "mock-responses", required_if_eq("backend", "mock"))]
+    mock_responses: Option<PathBuf>,
+
+    #[command(flatten)]
+    model: ModelArgs,
+
+    #[command(flatten)]
+    openai: OpenAiArgs,
+}
+
+/// the OpenAI-compatible endpoint of the openai backend
+#[derive(clap::Args, Debug, Clone)]
+pub struct OpenAiArgs {
+    /// The base URL of the OpenAI-compatible API, /completions is appended
+    #[arg(long = "base-url", default_value = "http://localhost:8080/v1")]
+    base_url: String,
+
+    /// The model name sent to the OpenAI-compatible API
+    #[arg(long = "model-name", default_value = "bonito")]
+    model_name: String,
+
+    /// The API key of the OpenAI-compatible API, $OPENAI_API_KEY by default
+    #[arg(long = "api-key")]
+    api_key: Option<String>,
+
+    /// Timeout of each request in seconds
+    #[arg(long = "timeout", default_value_t = 120)]
+    timeout: u64,
+
+    /// How many times a request is retried on connection errors, 429 and 5xx
+    #[arg(long = "retries", default_value_t = 2)]
+    retries: u32,
+}
+
+impl OpenAiArgs {
+    fn config(&self) -> OpenAiConfig {
+        OpenAiConfig {
+            base_url: self.base_url.clone(),
+            model: self.model_name.clone(),
+            api_key: self
+                .api_key
+                .clone()
+                .or_else(|| std::env::var("OPENAI_API_KEY").ok()),
+            timeout: Duration::from_secs(self.timeout),
+            retries: self.retries,
+        }
+    }
+}
+
+/// a backend ready to be created, its model is already downloaded
+pub enum BackendConfig {
+    #[cfg(feature = "llama")]
+    Llama(PathBuf),
+    OpenAi(OpenAiConfig),
+    Mock(Vec<String>),
+}
+
+impl BackendArgs {
+    /// downloads the model of the backend if needed
+    pub async fn resolve(&self) -> Result<BackendConfig> {
+        let backend = match self.backend {
+            Some(backend) => backend,
+            #[cfg(feature = "llama")]
+            None => Backend::Llama,
+            #[cfg(not(feature = "llama"))]
+            None => Backend::OpenAi,
+        };
+        match backend {
+            #[cfg(feature = "llama")]
+            Backend::Llama => Ok(BackendConfig::Llama(resolve_model_path(&self.model).await?)),
+            Backend::OpenAi => Ok(BackendConfig::OpenAi(self.openai.config())),
+            Backend::Mock => {
+                let path = self
+                    .mock_responses
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("--mock-responses is required by the mock backend"))?;
+                let responses = std::fs::read_to_string(path)
+                    .with_context(|| format!("unable to read {}", path.display()))?
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(serde_json::from_str)
+                    .collect::<Result<Vec<String>, _>>()
+                    .with_context(|| {
+                        format!("{} is not a JSONL file of strings", path.display())
+                    })?;
+                Ok(BackendConfig::Mock(responses))
+            }
+        }
+    }
+}
+
+impl BackendConfig {
+    /// creates the generator of batches of up to `parallel` prompts and hands it to `f`,
+    /// the model stays loaded while `f` runs
+    #[cfg_attr(not(feature = "llama"), allow(unused_variables))]
+    pub fn with_generator<R>(
+        self,
+        parallel: usize,
+        f: impl for<'a> FnOnce(Box<dyn Generator + 'a>) -> Result<R>,
+    ) -> Result<R> {
+        match self {
+            #[cfg(feature = "llama")]
+            BackendConfig::Llama(model_path) => {
+                let bonito = Bonito::load(&model_path)?.with_parallel(parallel);
+                let session = bonito.session()?;
+                f(Box::new(session))
+            }
+            BackendConfig::OpenAi(config) => f(Box::new(OpenAiGenerator::new(config))),
+            BackendConfig::Mock(responses) => f(Box::new(MockGenerator::new(responses))),
+        }
+    }
+}
+
+/// where to load the GGUF model from
+#[derive(clap::Args, Debug, Clone)]
+#[cfg_attr(not(feature = "llama"), allow(dead_code))]
+pub struct ModelArgs {
+    /// Load the model from a local GGUF file instead of the huggingface hub
+    #[arg(long = "model-path")]
+    model_path: Option<PathBuf>,
+
+    /// The huggingface repo to download the model from
+    #[arg(long = "model-repo", default_value = DEFAULT_MODEL_REPO)]
+    model_repo: String,
+
+    /// The GGUF file in the huggingface repo
+    #[arg(long = "model-file", default_value = DEFAULT_MODEL_FILE)]
+    model_file: String,
+
+    /// The revision (branch, tag or commit) of the huggingface repo
+    #[arg(long = "revision", default_value = "main")]
+    revision: String,
+
+    /// Never download, only look for the model in the local huggingface cache
+    #[arg(long = "offline")]
+    offline: bool,
+}
+
+/// returns the local path of the GGUF model, downloads it from the huggingface hub if needed
+#[cfg(feature = "llama")]
+pub async fn resolve_model_path(model: &ModelArgs) -> Result<PathBuf> {
+    if let Some(model_path) = &model.model_path {
+        if !model_path.is_file() {
+            bail!("the model file {} does not exist", model_path.display());
+        }
+        return Ok(model_path.clone());
+    }
+
+    let hub_model = HubModel {
+        repo: model.model_repo.clone(),
+        file: model.model_file.clone(),
+        revision: model.revision.clone(),
+    };
+
+    if model.offline {
+        return hub_model.cached_path().ok_or_else(|| {
+            anyhow!(
+                "{} is not in the huggingface cache {}, download it first or use --model-path",
+                hub_model,
+                hf_hub::Cache::default().path().display()
+            )
+        });
+    }
+
+    hub_model
+        .download(true)
+        .await
+        .with_context(|| format!("unable to download {}", hub_model))
+}
+
+/// parses the `--task` argument, "all" expands to every task type
+pub fn parse_task_types(task: &str) -> Result<Vec<TaskType>> {
+    if task == "all" {
+        return Ok(TASK_TYPES.to_vec());
+    }
+    match str_to_task_type(task) {
+        Some(task_type) => Ok(vec![task_type]),
+        None => bail!(
+            "unknown task \"{task}\", expected \"all\" or one of: {}",
+            TASK_TYPES
+                .map(|task_type| task_type_to_str(&task_type))
+                .join(", ")
+        ),
+    }
+}
@@ -0,0 +1,520 @@
+use anyhow::Result;
+use bonito::generator::{FinishReason, Generator};
+use bonito::prepare_prompt_with;
+use bonito::retry::{generate_parsed, generate_parsed_streaming, Attempt, FailedTask, RetryPolicy};
+use bonito::task_type_to_str;
+use bonito::ParsedTask;
+use bonito::TaskFields;
+use bonito::TaskType;
+use clap::builder::RangedU64ValueParser;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::args::{parse_task_types, BackendArgs, ContextPolicyArgs, SamplingArgs};
+use crate::input::{rejects_path, InputArgs, InvalidRecord, JsonlWriter};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct GenerateArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Where to write the parsed tasks as JSONL, stdout by default
+    #[arg(short = 'o', long = "output", requires = "input")]
+    output: Option<PathBuf>,
+
+    /// Where to write the completions which failed as JSONL, <output>.rejects.jsonl by default
+    #[arg(long = "rejects", requires = "input")]
+    rejects: Option<PathBuf>,
+
+    /// How many prompts are generated in parallel, as sequences of the same batch
+    #[arg(long = "parallel", default_value_t = 4, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    parallel: usize,
+
+    /// How many completions to generate for each context and task type
+    #[arg(long = "samples", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..), requires = "input")]
+    samples: usize,
+
+    #[command(flatten)]
+    context_policy: ContextPolicyArgs,
+
+    /// How many times a completion is generated with a new seed until it parses (an empty answer, an answer
+    /// not found in the context...), the attempts are recorded in the tasks and the rejects
+    #[arg(long = "max-attempts", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_attempts: usize,
+
+    /// Added to the temperature at every new attempt
+    #[arg(long = "retry-temperature-step", default_value_t = 0.0)]
+    retry_temperature_step: f32,
+
+    /// Print the generated text of the test chunk as it is generated
+    #[arg(long = "stream", conflicts_with = "input")]
+    stream: bool,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    #[command(flatten)]
+    sampling: SamplingArgs,
+}
+
+/// a completion which could not be generated or parsed, written to the rejects file
+#[derive(Serialize)]
+struct Reject<'a> {
+    context: &'a str,
+    task_type: TaskType,
+    /// the prompt + generated string, empty if the generation failed
+    completion: &'a str,
+    /// None if the generation failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<FinishReason>,
+    error: String,
+    /// every attempt with --max-attempts
+    #[serde(skip_serializing_if = "<[Attempt]>::is_empty")]
+    attempts: &'a [Attempt],
+}
+
+/// a record of the corpus which could not be read, written to the rejects file like the rejects of `parse`
+#[derive(Serialize)]
+struct InvalidRecordReject {
+    line: usize,
+    reason: &'static str,
+    error: String,
+}
+
+/// writes the parsed tasks and the rejects of a corpus
+struct TaskWriter {
+    output: JsonlWriter,
+    rejects: Option<JsonlWriter>,
+    n_parsed: usize,
+    n_rejected: usize,
+    n_invalid_records: usize,
+}
+
+impl TaskWriter {
+    /// writes the parsed task, or a reject if every attempt failed to parse
+    fn write(
+        &mut self,
+        context: &str,
+        task_type: TaskType,
+        task: Result<ParsedTask, FailedTask>,
+    ) -> Result<()> {
+        match task {
+            Ok(task) => {
+                self.n_parsed += 1;
+                self.output.write(&task)
+            }
+            Err(failed) => self.reject(Reject {
+                context,
+                task_type,
+                completion: &failed.completion.text,
+                finish_reason: Some(failed.completion.finish_reason),
+                error: failed.error.to_string(),
+                attempts: &failed.attempts,
+            }),
+        }
+    }
+
+    /// writes a reject of a context without a completion, e.g. if the generation failed
+    fn write_error(&mut self, context: &str, task_type: TaskType, error: String) -> Result<()> {
+        self.reject(Reject {
+            context,
+            task_type,
+            completion: "",
+            finish_reason: None,
+            error,
+            attempts: &[],
+        })
+    }
+
+    /// writes a reject of a record of the corpus which could not be read
+    fn write_invalid_record(&mut self, line: usize, error: String) -> Result<()> {
+        self.n_invalid_records += 1;
+        match &mut self.rejects {
+            Some(rejects) => rejects.write(&InvalidRecordReject {
+                line,
+                reason: "invalid_record",
+                error,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn reject(&mut self, reject: Reject) -> Result<()> {
+        self.n_rejected += 1;
+        match &mut self.rejects {
+            Some(rejects) => rejects.write(&reject),
+            None => Ok(()),
+        }
+    }
+}
+
+pub async fn run(args: GenerateArgs) -> Result<()> {
+    args.backend
+        .resolve()
+        .await?
+        .with_generator(args.parallel, |generator| run_with(generator, &args))
+}
+
+/// generates the tasks of the test chunk or of every context of the input with the generator
+fn run_with(mut generator: impl Generator, args: &GenerateArgs) -> Result<()> {
+    let task_types = parse_task_types(&args.input.task)?;
+    let params = args.sampling.sampling_params()?;
+    let retry = RetryPolicy {
+        max_attempts: args.max_attempts,
+        temperature_step: args.retry_temperature_step,
+    };
+
+    if args.input.path.is_none() {
+        let test_chunk = args.input.test_chunk.clone().unwrap_or_default();
+        // the streamed task types are generated one at a time, their texts would be mixed up otherwise
+        let chunk_size = if args.stream { 1 } else { args.parallel };
+        for (i, task_types_chunk) in task_types.chunks(chunk_size).enumerate() {
+            let params = params.offset_seed(i * chunk_size);
+            let prompts = task_types_chunk
+                .iter()
+                .map(|task_type| {
+                    prepare_prompt_with(&test_chunk, task_type, args.context_policy.policy)
+                })
+                .collect::<Result<Vec<String>, _>>()?;
+            let prompts: Vec<&str> = prompts.iter().map(String::as_str).collect();
+            let print_task_type = |task_type: &TaskType| {
+                if task_types.len() > 1 {
+                    println!("task: {}", task_type_to_str(task_type));
+                }
+            };
+
+            let tasks = if args.stream {
+                print_task_type(&task_types_chunk[0]);
+                let mut attempt = 0;
+                let tasks = generate_parsed_streaming(
+                    &mut generator,
+                    &prompts,
+                    &params,
+                    &retry,
+                    args.context_policy.policy,
+                    &mut |delta| {
+                        if delta.attempt != attempt {
+                            attempt = delta.attempt;
+                            println!("\n(attempt {})", attempt + 1);
+                        }
+                        print!("{}", delta.text);
+                        let _ = std::io::stdout().flush();
+                    },
+                )?;
+                println!("\n");
+                tasks
+            } else {
+                generate_parsed(
+                    &mut generator,
+                    &prompts,
+                    &params,
+                    &retry,
+                    args.context_policy.policy,
+                )?
+            };
+
+            for (task_type, task) in task_types_chunk.iter().zip(tasks) {
+                if !args.stream {
+                    print_task_type(task_type);
+                }
+                let attempts = match task {
+                    Ok(parsed) => {
+                        print_task(&parsed);
+                        if let Some(finish_reason) = parsed.finish_reason {
+                            println!("finish reason: {}", finish_reason);
+                        }
+                        parsed.attempts
+                    }
+                    Err(failed) => {
+                        println!(
+                            "failed to parse the completion ({}), here is the completion:\n{}",
+                            failed.error, &failed.completion.text
+                        );
+                        println!("finish reason: {}", failed.completion.finish_reason);
+                        failed.attempts
+                    }
+                };
+                for (i, attempt) in attempts.iter().enumerate() {
+                    if let Some(error) = &attempt.error {
+                        println!("attempt {} rejected: {}", i + 1, error);
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let contexts = args.input.contexts()?;
+
+    let rejects = args
+        .rejects
+        .clone()
+        .or_else(|| args.output.as_deref().map(rejects_path));
+    let mut writer = TaskWriter {
+        output: JsonlWriter::create(args.output.as_deref())?,
+        rejects: rejects
+            .as_deref()
+            .map(|path| JsonlWriter::create(Some(path)))
+            .transpose()?,
+        n_parsed: 0,
+        n_rejected: 0,
+        n_invalid_records: 0,
+    };
+
+    // every (context, task type, sample) is a job with its prompt, `--parallel` jobs are generated together
+    let mut jobs: Vec<(String, TaskType, String)> = vec![];
+    let mut n_contexts = 0;
+    // the jobs generated so far, the seed offset of the next batch so that the samples of a context differ
+    let mut n_jobs = 0;
+    let mut contexts = contexts.peekable();
+    while let Some(context) = contexts.next() {
+        match context {
+            Ok(context) => {
+                n_contexts += 1;
+                for task_type in &task_types {
+                    let prompt = match prepare_prompt_with(
+                        &context,
+                        task_type,
+                        args.context_policy.policy,
+                    ) {
+                        Ok(prompt) => prompt,
+                        Err(err) => {
+                            writer.write_error(&context, *task_type, err.to_string())?;
+                            continue;
+                        }
+                    };
+                    for _ in 0..args.samples {
+                        jobs.push((context.clone(), *task_type, prompt.clone()));
+                    }
+                }
+            }
+            // a record which can't be read is rejected, the jobs of the other contexts go on
+            Err(err) => match err.downcast_ref::<InvalidRecord>() {
+                Some(record) => {
+                    eprintln!("{err:#}, skipped");
+                    writer.write_invalid_record(record.line, format!("{err:#}"))?;
+                }
+                None => return Err(err),
+            },
+        }
+
+        let is_last = contexts.peek().is_none();
+        while jobs.len() >= args.parallel || (is_last && !jobs.is_empty()) {
+            let batch: Vec<(String, TaskType, String)> =
+                jobs.drain(..args.parallel.min(jobs.len())).collect();
+            let prompts: Vec<&str> = batch.iter().map(|(_, _, prompt)| prompt.as_str()).collect();
+            let batch_params = params.offset_seed(n_jobs);
+            n_jobs += batch.len();
+
+            match generate_parsed(
+                &mut generator,
+                &prompts,
+                &batch_params,
+                &retry,
+                args.context_policy.policy,
+            ) {
+                Ok(tasks) => {
+                    for ((context, task_type, _), task) in batch.iter().zip(tasks) {
+                        writer.write(context, *task_type, task)?;
+                    }
+                }
+                Err(err) => {
+                    for (context, task_type, _) in &batch {
+                        writer.write_error(context, *task_type, format!("{err:#}"))?;
+                    }
+                }
+            }
+            eprintln!(
+                "{} contexts, {} invalid records, {} tasks, {} rejected",
+                n_contexts, writer.n_invalid_records, writer.n_parsed, writer.n_rejected
+            );
+        }
+    }
+
+    if writer.rejects.is_none() && writer.n_rejected + writer.n_invalid_records > 0 {
+        eprintln!(
+            "{} rejected completions and {} invalid records were not written, use --rejects to keep them",
+            writer.n_rejected, writer.n_invalid_records
+        );
+    }
+
+    Ok(())
+}
+
+/// prints the task specific fields of a parsed completion
+fn print_task(parsed: &ParsedTask) {
+    let completion = &parsed.completion;
+    match &parsed.fields {
+        TaskFields::ExtractiveQuestionAnswering { question, answer } => {
+            println!("q: {}", question);
+            println!("a: {}", answer.text);
+            println!("answer_start: {}", answer.char_start);
+        }
+        TaskFields::MultipleChoice(mcqa) => {
+            println!("q: {}", mcqa.question);
+            for (i, option) in mcqa.options.iter().enumerate() {
+                let mark = if mcqa.answer_index == Some(i) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}. {}", mark, i + 1, option);
+            }
+            println!("a: {}", mcqa.answer);
+            if mcqa.answer_mismatch {
+                println!("(the answer doesn't match any option)");
+            }
+        }
+        TaskFields::Inference(inference) => {
+            println!("premise: {}", inference.premise);
+            println!("hypothesis: {}", inference.hypothesis);
+            println!("label: {:?} ({})", inference.label, inference.raw_label);
+        }
+        TaskFields::Classification(label) => {
+            println!("instruction: {}", completion.rendered_instruction);
+            println!("labels: {}", label.labels.join(", "));
+            println!("label: {} ({})", label.label, label.raw_label);
+        }
+        TaskFields::Generic => {
+            println!("instruction: {}", completion.rendered_instruction);
+            println!("response: {}", completion.response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse_args, read_records, TestDir};
+    use bonito::generator::MockGenerator;
+
+    #[test]
+    fn test_generate_corpus() {
+        let dir = TestDir::new("generate");
+        let input = dir.join("corpus.jsonl");
+        let output = dir.join("out.jsonl");
+        std::fs::write(
+            &input,
+            "{\"text\": \"Paris is the capital of France.\"}\n{\"text\": \"Bern is the capital of Switzerland.\"}\n",
+        )
+        .unwrap();
+
+        let args: GenerateArgs = parse_args([
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+            "--parallel".as_ref(),
+            "3".as_ref(),
+        ]);
+        let generator =
+            MockGenerator::new(["{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis"]);
+        run_with(generator, &args).unwrap();
+
+        let records = read_records(&output);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["task_type"], "exqa");
+        assert_eq!(records[0]["answer"]["text"], "Paris");
+        assert_eq!(records[0]["finish_reason"], "eos");
+
+        // "Paris" is not in the context about Bern
+        let rejects = read_records(&dir.join("out.rejects.jsonl"));
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0]["context"], "Bern is the capital of Switzerland.");
+        assert_eq!(
+            rejects[0]["error"],
+            "the answer is not found in the context"
+        );
+
+        // the context about Bern is generated again and gets "Bern"
+        let args: GenerateArgs = parse_args([
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+            "--max-attempts".as_ref(),
+            "3".as_ref(),
+        ]);
+        let generator = MockGenerator::new([
+            "{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis",
+            "{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis",
+            "{{context}}\nWhat is the capital of Switzerland?\n<|pipe|>\nBern",
+        ]);
+        run_with(generator, &args).unwrap();
+
+        let records = read_records(&output);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["answer"]["text"], "Bern");
+        let attempts = records[1]["attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0]["reason"], "ungrounded_answer");
+        assert!(attempts[1].get("reason").is_none());
+    }
+
+    #[test]
+    fn test_generate_invalid_record() {
+        let dir = TestDir::new("invalid-record");
+        let input = dir.join("corpus.jsonl");
+        let output = dir.join("out.jsonl");
+        std::fs::write(
+            &input,
+            "{\"text\": \"Paris is the capital of France.\"}\n{\"text\": \n{\"text\": \"Paris is big.\"}\n",
+        )
+        .unwrap();
+
+        // the context before the bad line is still in the jobs when it's read
+        let args: GenerateArgs = parse_args([
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+            "--parallel".as_ref(),
+            "2".as_ref(),
+        ]);
+        let generator =
+            MockGenerator::new(["{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis"]);
+        run_with(generator, &args).unwrap();
+
+        let records = read_records(&output);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["context"], "Paris is big.");
+
+        let rejects = read_records(&dir.join("out.rejects.jsonl"));
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0]["line"], 2);
+        assert_eq!(rejects[0]["reason"], "invalid_record");
+        assert!(rejects[0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("corpus.jsonl:2: invalid record"));
+    }
+
+    #[test]
+    fn test_generate_samples_seeds() {
+        let dir = TestDir::new("samples");
+        let input = dir.join("corpus.txt");
+        std::fs::write(&input, "Paris is the capital of France.\n").unwrap();
+
+        // 5 samples in batches of 2, every sample samples with its own seed
+        let args: GenerateArgs = parse_args([
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--output".as_ref(),
+            dir.join("out.jsonl").as_os_str(),
+            "--samples".as_ref(),
+            "5".as_ref(),
+            "--parallel".as_ref(),
+            "2".as_ref(),
+            "--seed".as_ref(),
+            "7".as_ref(),
+        ]);
+        let mut generator =
+            MockGenerator::new(["{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis"]);
+        run_with(&mut generator, &args).unwrap();
+        assert_eq!(
+            generator.seeds,
+            [Some(7), Some(8), Some(9), Some(10), Some(11)]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// format of the corpus file given to `--input`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// one JSON object per line, the context is in `--context-field`
+    Jsonl,
+    /// a CSV file with a header row, the context is in the `--context-field` column
+    Csv,
+    /// one context per non-empty line
+    Text,
+}
+
+impl InputFormat {
+    /// guesses the format from the file extension, jsonl if the extension is unknown
+    pub fn from_path(path: &Path) -> InputFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => InputFormat::Csv,
+            Some("txt") | Some("text") => InputFormat::Text,
+            _ => InputFormat::Jsonl,
+        }
+    }
+}
+
+/// the contexts to build the prompts from, a test chunk or a corpus file, and their task types
+#[derive(clap::Args, Debug, Clone)]
+pub struct InputArgs {
+    /// The text to build the prompts from, as the only context
+    #[arg(
+        short = 't',
+        long = "test-chunk",
+        required_unless_present = "input",
+        conflicts_with = "input"
+    )]
+    pub test_chunk: Option<String>,
+
+    /// A corpus file (.jsonl, .csv or .txt) to build the prompts from, one per context
+    #[arg(id = "input", short = 'i', long = "input", value_name = "INPUT")]
+    pub path: Option<PathBuf>,
+
+    /// The format of --input, guessed from the file extension by default
+    #[arg(
+        long = "input-format",
+        value_name = "INPUT_FORMAT",
+        value_enum,
+        requires = "input"
+    )]
+    pub format: Option<InputFormat>,
+
+    /// The JSON field (jsonl) or column (csv) holding the context
+    #[arg(long = "context-field", default_value = "text")]
+    pub context_field: String,
+
+    /// The task type of the prompts: exqa, mcqa, qg, qa, ynqa, coref, paraphrase, paraphrase_id,
+    /// sent_comp, sentiment, summarization, text_gen, topic_class, wsd, te, nli or all
+    #[arg(long = "task", default_value = "exqa")]
+    pub task: String,
+}
+
+impl InputArgs {
+    /// the contexts of the corpus file, or the test chunk
+    pub fn contexts(&self) -> Result<Box<dyn Iterator<Item = Result<String>>>> {
+        match &self.path {
+            Some(path) => read_contexts(
+                path,
+                self.format.unwrap_or_else(|| InputFormat::from_path(path)),
+                &self.context_field,
+            ),
+            None => Ok(Box::new(std::iter::once(Ok(self
+                .test_chunk
+                .clone()
+                .unwrap_or_default())))),
+        }
+    }
+}
+
+/// a record of a corpus file which can't be read, the error of its context with the underlying error as the source,
+/// the corpus can be read on past it
+#[derive(Debug)]
+pub struct InvalidRecord {
+    pub source: String,
+    /// the line of the record in the file, from 1
+    pub line: usize,
+}
+
+impl std::fmt::Display for InvalidRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: invalid record", self.source, self.line)
+    }
+}
+
+/// reads the contexts of a corpus file lazily, one per JSON line, CSV row or non-empty text line,
+/// the records which can't be read are `InvalidRecord` errors
+pub fn read_contexts(
+    path: &Path,
+    format: InputFormat,
+    context_field: &str,
+) -> Result<Box<dyn Iterator<Item = Result<String>>>> {
+    let open = || File::open(path).with_context(|| format!("unable to open {}", path.display()));
+    let source = path.display().to_string();
+
+    match format {
+        InputFormat::Jsonl => {
+            let context_field = context_field.to_string();
+            let lines = BufReader::new(open()?).lines().enumerate();
+            Ok(Box::new(lines.filter_map(move |(i, line)| {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => return Some(Err(err.into())),
+                };
+                if line.trim().is_empty() {
+                    return None;
+                }
+                Some(jsonl_context(&line, &context_field).context(InvalidRecord {
+                    source: source.clone(),
+                    line: i + 1,
+                }))
+            })))
+        }
+        InputFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(open()?);
+            let column = reader
+                .headers()
+                .with_context(|| format!("unable to read the header of {}", source))?
+                .iter()
+                .position(|header| header == context_field)
+                .ok_or_else(|| anyhow!("{} has no \"{}\" column", source, context_field))?;
+            Ok(Box::new(reader.into_records().enumerate().map(
+                move |(i, record)| {
+                    let record = record.map_err(|err| {
+                        // the header is the line 1, a row may span several lines
+                        let line = err
+                            .position()
+                            .map_or(i + 2, |position| position.line() as usize);
+                        anyhow::Error::new(err).context(InvalidRecord {
+                            source: source.clone(),
+                            line,
+                        })
+                    })?;
+                    Ok(record.get(column).unwrap_or_default().to_string())
+                },
+            )))
+        }
+        InputFormat::Text => {
+            let lines = BufReader::new(open()?).lines();
+            Ok(Box::new(lines.filter_map(|line| match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(Ok(line.trim().to_string())),
+                Err(err) => Some(Err(err.into())),
+            })))
+        }
+    }
+}
+
+fn jsonl_context(line: &str, context_field: &str) -> Result<String> {
+    let record: serde_json::Value = serde_json::from_str(line)?;
+    record
+        .get(context_field)
+        .and_then(|context| context.as_str())
+        .map(|context| context.to_string())
+        .ok_or_else(|| anyhow!("no string field \"{}\"", context_field))
+}
+
+/// the default rejects file of an output file, e.g. out.jsonl -> out.rejects.jsonl
+pub fn rejects_path(output: &Path) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    output.with_file_name(format!("{}.rejects.jsonl", stem))
+}
+
+/// writes one JSON record per line to a file, or to stdout if there is no path
+pub struct JsonlWriter {
+    writer: BufWriter<Box<dyn Write>>,
+}
+
+impl JsonlWriter {
+    pub fn create(path: Option<&Path>) -> Result<Self> {
+        let writer: Box<dyn Write> = match path {
+            Some(path) => Box::new(
+                File::create(path)
+                    .with_context(|| format!("unable to create {}", path.display()))?,
+            ),
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(Self {
+            writer: BufWriter::new(writer),
+        })
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)?;
+        // flush every record so a long run can be followed and interrupted without losing tasks
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(name: &str, content: &str, context_field: &str) -> Result<Vec<String>> {
+        let path = std::env::temp_dir().join(format!("bonitox-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content)?;
+        let contexts = read_contexts(&path, InputFormat::from_path(&path), context_field)?
+            .collect::<Result<Vec<_>>>();
+        std::fs::remove_file(&path)?;
+        contexts
+    }
+
+    #[test]
+    fn test_read_contexts() {
+        let contexts = read_all(
+            "corpus.jsonl",
+            "{\"id\": 1, \"text\": \"first\"}\n\n{\"id\": 2, \"text\": \"second\"}\n",
+            "text",
+        )
+        .unwrap();
+        assert_eq!(contexts, vec!["first", "second"]);
+
+        let contexts = read_all(
+            "corpus.csv",
+            "id,body\n1,\"first, with a comma\"\n2,second\n",
+            "body",
+        )
+        .unwrap();
+        assert_eq!(contexts, vec!["first, with a comma", "second"]);
+
+        let contexts = read_all("corpus.txt", "first\n\n  second  \n", "text").unwrap();
+        assert_eq!(contexts, vec!["first", "second"]);
+
+        let err = read_all("missing-field.jsonl", "\n{\"id\": 1}\n", "text").unwrap_err();
+        assert_eq!(err.downcast_ref::<InvalidRecord>().unwrap().line, 2);
+        assert!(read_all("missing-column.csv", "id\n1\n", "text").is_err());
+    }
+
+    #[test]
+    fn test_rejects_path() {
+        assert_eq!(
+            rejects_path(Path::new("data/out.jsonl")),
+            PathBuf::from("data/out.rejects.jsonl")
+        );
+    }
+}
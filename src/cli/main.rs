@@ -0,0 +1,45 @@
+mod args;
+mod generate;
+mod input;
+mod parse;
+mod prompt;
+mod serve;
+#[cfg(test)]
+mod test_support;
+
+use anyhow::Result;
+use clap::Parser;
+
+use generate::GenerateArgs;
+use parse::ParseArgs;
+use prompt::PromptArgs;
+use serve::ServeArgs;
+
+#[derive(clap::Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Generate tasks from a text or from every context of a corpus file
+    Generate(GenerateArgs),
+    /// Parse stored completions again without a model, e.g. after a parser change
+    Parse(ParseArgs),
+    /// Write the prompts of a text or of every context of a corpus file without generating, with token counts
+    Prompt(PromptArgs),
+    /// Serve task generation and parsing over HTTP
+    Serve(ServeArgs),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Generate(args) => generate::run(args).await,
+        Command::Parse(args) => parse::run(args),
+        Command::Prompt(args) => prompt::run(args).await,
+        Command::Serve(args) => serve::run(args).await,
+    }
+}
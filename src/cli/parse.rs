@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Context, Result};
+use bonito::parse_task_with;
+use bonito::prepare_prompt_with;
+use bonito::sanitize::ContextError;
+use bonito::TaskType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use crate::args::ContextPolicyArgs;
+use crate::input::{rejects_path, JsonlWriter};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ParseArgs {
+    /// A JSONL file of stored completions, e.g. the tasks or the rejects of `bonitox generate`
+    #[arg(short = 'i', long = "input")]
+    input: PathBuf,
+
+    /// Where to write the parsed tasks as JSONL, stdout by default
+    #[arg(short = 'o', long = "output")]
+    output: Option<PathBuf>,
+
+    /// Where to write the completions which failed to parse as JSONL, <output>.rejects.jsonl by default
+    #[arg(long = "rejects")]
+    rejects: Option<PathBuf>,
+
+    /// The JSON field holding the completion, the prompt + generated string or only the generated string
+    #[arg(long = "completion-field", default_value = "completion")]
+    completion_field: String,
+
+    /// The JSON field holding the original context, used to rebuild the prompt of a generated string
+    #[arg(long = "context-field", default_value = "context")]
+    context_field: String,
+
+    /// The JSON field holding the task type (exqa, mcqa...), used to rebuild the prompt of a generated string
+    #[arg(long = "task-type-field", default_value = "task_type")]
+    task_type_field: String,
+
+    #[command(flatten)]
+    context_policy: ContextPolicyArgs,
+}
+
+/// a stored completion which failed to parse, written to the rejects file
+#[derive(Serialize)]
+struct Reject<'a> {
+    /// the line of the completion in the input
+    line: usize,
+    /// the prompt + generated string, empty if the record is invalid
+    completion: &'a str,
+    /// the kind of parse error, e.g. "missing_pipe", "invalid_record" or "rejected_context" if the record
+    /// can't be read or its prompt rebuilt
+    reason: &'static str,
+    error: String,
+}
+
+/// the number of parsed completions and of failures by reason
+#[derive(Debug, Default, PartialEq)]
+struct ParseSummary {
+    n_parsed: usize,
+    failures: BTreeMap<&'static str, usize>,
+}
+
+impl std::fmt::Display for ParseSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n_rejected: usize = self.failures.values().sum();
+        write!(
+            f,
+            "{} completions, {} parsed, {} rejected",
+            self.n_parsed + n_rejected,
+            self.n_parsed,
+            n_rejected
+        )?;
+        let mut failures: Vec<_> = self.failures.iter().collect();
+        failures.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (reason, count) in failures {
+            write!(f, "\n{:>8} {}", count, reason)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(args: ParseArgs) -> Result<()> {
+    let summary = parse_file(&args)?;
+    eprintln!("{}", summary);
+    Ok(())
+}
+
+/// parses every stored completion of the input without a model, writes the tasks and the rejects
+fn parse_file(args: &ParseArgs) -> Result<ParseSummary> {
+    let source = args.input.display().to_string();
+    let lines = BufReader::new(
+        File::open(&args.input).with_context(|| format!("unable to open {}", source))?,
+    )
+    .lines();
+
+    let mut output = JsonlWriter::create(args.output.as_deref())?;
+    let rejects = args
+        .rejects
+        .clone()
+        .or_else(|| args.output.as_deref().map(rejects_path));
+    let mut rejects = rejects
+        .as_deref()
+        .map(|path| JsonlWriter::create(Some(path)))
+        .transpose()?;
+
+    let mut summary = ParseSummary::default();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // a record which can't be read is rejected like a completion which can't be parsed
+        let (completion, result) = match stored_completion(&line, args) {
+            Ok(completion) => {
+                let result = parse_task_with(&completion, args.context_policy.policy)
+                    .map_err(|err| (err.kind(), err.to_string()));
+                (completion, result)
+            }
+            Err(err) => {
+                let reason = if err.is::<ContextError>() {
+                    "rejected_context"
+                } else {
+                    "invalid_record"
+                };
+                (String::new(), Err((reason, format!("{err:#}"))))
+            }
+        };
+
+        match result {
+            Ok(parsed) => {
+                summary.n_parsed += 1;
+                output.write(&parsed)?;
+            }
+            Err((reason, error)) => {
+                *summary.failures.entry(reason).or_default() += 1;
+                if let Some(rejects) = &mut rejects {
+                    rejects.write(&Reject {
+                        line: i + 1,
+                        completion: &completion,
+                        reason,
+                        error,
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// the prompt + generated string of a record, the prompt is rebuilt from the context and the task type
+/// if the completion is only the generated string
+fn stored_completion(line: &str, args: &ParseArgs) -> Result<String> {
+    let record: serde_json::Value = serde_json::from_str(line)?;
+    let completion = record
+        .get(&args.completion_field)
+        .and_then(|completion| completion.as_str())
+        .ok_or_else(|| anyhow!("no string field \"{}\"", args.completion_field))?;
+    if completion.contains("<|tasktype|>") {
+        return Ok(completion.to_string());
+    }
+
+    let context = record
+        .get(&args.context_field)
+        .and_then(|context| context.as_str());
+    let task_type = record
+        .get(&args.task_type_field)
+        .map(TaskType::deserialize)
+        .transpose()?;
+    match (context, task_type) {
+        (Some(context), Some(task_type)) => Ok(format!(
+            "{}{}",
+            prepare_prompt_with(context, &task_type, args.context_policy.policy)?,
+            completion
+        )),
+        // parse_task reports the missing prompt
+        _ => Ok(completion.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse_args, read_records, TestDir};
+    use bonito::generator::{parse_generated, Completion, FinishReason};
+    use bonito::prepare_prompt;
+
+    #[test]
+    fn test_parse_file() {
+        let dir = TestDir::new("parse");
+        let input = dir.join("completions.jsonl");
+        let output = dir.join("out.jsonl");
+
+        let prompt = prepare_prompt(
+            "Paris is the capital of France.",
+            &TaskType::ExtractiveQuestionAnswering,
+        );
+        let records = [
+            // the prompt + generated string
+            serde_json::json!({"completion": format!("{}{{{{context}}}}\nWhere?\n<|pipe|>\nParis", prompt)}),
+            // only the generated string, with its context and task type
+            serde_json::json!({
+                "context": "Rome is the capital of Italy.",
+                "task_type": "exqa",
+                "completion": "{{context}}\nWhere?\n<|pipe|>\nRome",
+            }),
+            serde_json::json!({
+                "context": "Bern is the capital of Switzerland.",
+                "task_type": "exqa",
+                "completion": "{{context}}\nWhere?\n<|pipe|>\nParis",
+            }),
+            serde_json::json!({"completion": format!("{}{{{{context}}}}\nWhere?\n<|pi", prompt)}),
+            serde_json::json!({"completion": "Where?\n<|pipe|>\nParis"}),
+        ];
+        let content: Vec<String> = records.iter().map(|record| record.to_string()).collect();
+        std::fs::write(&input, content.join("\n")).unwrap();
+
+        let args: ParseArgs = parse_args([
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+        ]);
+        let summary = parse_file(&args).unwrap();
+        assert_eq!(summary.n_parsed, 2);
+        assert_eq!(
+            summary.failures,
+            BTreeMap::from([
+                ("missing_task_type_marker", 1),
+                ("truncated_completion", 1),
+                ("ungrounded_answer", 1),
+            ])
+        );
+        assert!(summary
+            .to_string()
+            .starts_with("5 completions, 2 parsed, 3 rejected"));
+
+        let parsed = read_records(&output);
+        assert_eq!(parsed[1]["context"], "Rome is the capital of Italy.");
+        assert_eq!(parsed[1]["answer"]["text"], "Rome");
+
+        let rejects = read_records(&dir.join("out.rejects.jsonl"));
+        assert_eq!(rejects.len(), 3);
+        assert_eq!(rejects[0]["line"], 3);
+        assert_eq!(rejects[0]["reason"], "ungrounded_answer");
+
+        // the tasks of `bonitox generate` keep their completion, the invalid records are rejected
+        let task = parse_generated(Completion {
+            text: format!("{}{{{{context}}}}\nWhere?\n<|pipe|>\nParis", prompt),
+            finish_reason: FinishReason::Eos,
+        })
+        .unwrap();
+        let content = [
+            serde_json::to_string(&task).unwrap(),
+            "{\"text\": \"no completion\"}".to_string(),
+            "not json".to_string(),
+        ];
+        std::fs::write(&input, content.join("\n")).unwrap();
+        let summary = parse_file(&args).unwrap();
+        assert_eq!(summary.n_parsed, 1);
+        assert_eq!(summary.failures, BTreeMap::from([("invalid_record", 2)]));
+        let rejects = read_records(&dir.join("out.rejects.jsonl"));
+        assert_eq!(rejects[0]["line"], 2);
+        assert_eq!(rejects[0]["error"], "no string field \"completion\"");
+    }
+}
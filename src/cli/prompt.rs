@@ -0,0 +1,234 @@
+#[cfg(not(feature = "llama"))]
+use anyhow::bail;
+use anyhow::Result;
+use bonito::prepare_prompt_with;
+use bonito::sampling::SamplingParams;
+use bonito::TaskType;
+use clap::builder::RangedU64ValueParser;
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[cfg(feature = "llama")]
+use crate::args::resolve_model_path;
+use crate::args::{parse_task_types, ContextPolicyArgs, ModelArgs};
+use crate::input::{InputArgs, JsonlWriter};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PromptArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Where to write the prompts as JSONL, stdout by default
+    #[arg(short = 'o', long = "output")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    context_policy: ContextPolicyArgs,
+
+    /// Tokenize the prompts with the vocabulary of the model to report token counts and over-length contexts
+    #[arg(long = "count-tokens")]
+    count_tokens: bool,
+
+    /// The max tokens of the planned run, for the over-length check and the budget estimate
+    #[arg(long = "max-tokens", default_value_t = SamplingParams::default().max_tokens)]
+    max_tokens: usize,
+
+    /// The samples per context and task type of the planned run, for the budget estimate
+    #[arg(long = "samples", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    samples: usize,
+
+    #[command(flatten)]
+    model: ModelArgs,
+}
+
+/// a prompt written by `bonitox prompt`
+#[derive(Serialize)]
+struct PromptRecord<'a> {
+    context: &'a str,
+    task_type: TaskType,
+    /// the exact prompt given to the model, `<|tasktype|>`...`<|task|>`
+    prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n_tokens: Option<usize>,
+    /// the prompt and `--max-tokens` don't fit in the context window of the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    over_length: Option<bool>,
+}
+
+/// returns the number of tokens of a prompt
+type CountTokens<'a> = Box<dyn Fn(&str) -> Result<usize> + 'a>;
+
+/// tokenizes the prompts with the vocabulary of a model
+struct TokenCounter<'a> {
+    count: CountTokens<'a>,
+    /// the context window of the model
+    n_ctx: usize,
+}
+
+/// the totals of the prompts, printed at the end of the run
+#[derive(Debug, Default, PartialEq)]
+struct PromptSummary {
+    n_prompts: usize,
+    /// the contexts rejected by `--context-policy reject`, without a prompt
+    n_rejected: usize,
+    /// only counted with `--count-tokens`
+    n_prompt_tokens: usize,
+    n_over_length: usize,
+}
+
+pub async fn run(args: PromptArgs) -> Result<()> {
+    if !args.count_tokens {
+        return run_with(&args, None);
+    }
+
+    #[cfg(feature = "llama")]
+    {
+        let model_path = resolve_model_path(&args.model).await?;
+        // only the vocabulary is loaded, not the weights
+        let vocab = bonito::llama::Vocab::load(&model_path)?;
+        let counter = TokenCounter {
+            count: Box::new(|text| Ok(vocab.count_tokens(text))),
+            n_ctx: vocab.n_ctx_train(),
+        };
+        run_with(&args, Some(counter))
+    }
+    #[cfg(not(feature = "llama"))]
+    bail!("bonitox was built without the llama feature, --count-tokens needs the vocabulary of the model")
+}
+
+/// writes the prompt of every context and task type, with the token counts if there is a counter
+fn run_with(args: &PromptArgs, counter: Option<TokenCounter>) -> Result<()> {
+    let task_types = parse_task_types(&args.input.task)?;
+    let contexts = args.input.contexts()?;
+    let mut output = JsonlWriter::create(args.output.as_deref())?;
+
+    let mut summary = PromptSummary::default();
+    for context in contexts {
+        let context = context?;
+        for task_type in &task_types {
+            let prompt = match prepare_prompt_with(&context, task_type, args.context_policy.policy)
+            {
+                Ok(prompt) => prompt,
+                Err(_) => {
+                    summary.n_rejected += 1;
+                    continue;
+                }
+            };
+            let n_tokens = counter
+                .as_ref()
+                .map(|counter| (counter.count)(&prompt))
+                .transpose()?;
+            let over_length = counter
+                .as_ref()
+                .zip(n_tokens)
+                .map(|(counter, n_tokens)| n_tokens + args.max_tokens > counter.n_ctx);
+
+            summary.n_prompts += 1;
+            summary.n_prompt_tokens += n_tokens.unwrap_or_default();
+            if over_length == Some(true) {
+                summary.n_over_length += 1;
+            }
+            output.write(&PromptRecord {
+                context: &context,
+                task_type: *task_type,
+                prompt: &prompt,
+                n_tokens,
+                over_length,
+            })?;
+        }
+    }
+
+    eprintln!("{} prompts", summary.n_prompts);
+    if summary.n_rejected > 0 {
+        eprintln!(
+            "{} prompts rejected by --context-policy reject",
+            summary.n_rejected
+        );
+    }
+    if let Some(counter) = &counter {
+        eprint!(
+            "{}",
+            budget(&summary, counter.n_ctx, args.max_tokens, args.samples)
+        );
+    }
+    Ok(())
+}
+
+/// the token counts and the generation budget of a run of `samples` completions per prompt
+fn budget(summary: &PromptSummary, n_ctx: usize, max_tokens: usize, samples: usize) -> String {
+    let n_generated = summary.n_prompts * samples * max_tokens;
+    format!(
+        "{} prompt tokens, {} prompts over the context window of {} tokens with --max-tokens {}\n\
+         estimated budget: {} completions, {} prompt tokens to decode and at most {} tokens to generate\n",
+        summary.n_prompt_tokens,
+        summary.n_over_length,
+        n_ctx,
+        max_tokens,
+        summary.n_prompts * samples,
+        summary.n_prompt_tokens * samples,
+        n_generated
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse_args, read_records, TestDir};
+    use bonito::prepare_prompt;
+
+    #[test]
+    fn test_prompt_corpus() {
+        let dir = TestDir::new("prompt");
+        let input = dir.join("corpus.txt");
+        let output = dir.join("prompts.jsonl");
+        std::fs::write(
+            &input,
+            "a short context\na much longer context than the other one\n",
+        )
+        .unwrap();
+
+        let args: PromptArgs = parse_args([
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+            "--task".as_ref(),
+            "nli".as_ref(),
+            "--max-tokens".as_ref(),
+            "10".as_ref(),
+        ]);
+        // one token per word
+        let counter = TokenCounter {
+            count: Box::new(|text| Ok(text.split_whitespace().count())),
+            n_ctx: 22,
+        };
+        run_with(&args, Some(counter)).unwrap();
+
+        let records = read_records(&output);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["task_type"], "nli");
+        assert_eq!(
+            records[0]["prompt"],
+            prepare_prompt("a short context", &TaskType::NaturalLanguageInference)
+        );
+        assert_eq!(records[0]["n_tokens"], 9);
+        assert_eq!(records[1]["n_tokens"], 14);
+        assert_eq!(records[0]["over_length"], false);
+        assert_eq!(records[1]["over_length"], true);
+    }
+
+    #[test]
+    fn test_budget() {
+        let summary = PromptSummary {
+            n_prompts: 10,
+            n_rejected: 0,
+            n_prompt_tokens: 1000,
+            n_over_length: 1,
+        };
+        assert_eq!(
+            budget(&summary, 2048, 512, 2),
+            "1000 prompt tokens, 1 prompts over the context window of 2048 tokens with --max-tokens 512\n\
+             estimated budget: 20 completions, 2000 prompt tokens to decode and at most 10240 tokens to generate\n"
+        );
+    }
+}
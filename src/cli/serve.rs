@@ -0,0 +1,513 @@
+use anyhow::{anyhow, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bonito::generator::{parse_generated_with, GenerateError, Generator};
+use bonito::parse_task_with;
+use bonito::prepare_prompt_with;
+use bonito::sampling::SamplingParams;
+use bonito::sanitize::ContextPolicy;
+use bonito::ParsedTask;
+use bonito::TaskType;
+use clap::builder::RangedU64ValueParser;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+use crate::args::{BackendArgs, ContextPolicyArgs, SamplingArgs};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// The address to listen on
+    #[arg(long = "host", default_value = "127.0.0.1")]
+    host: String,
+
+    /// The port to listen on
+    #[arg(long = "port", default_value_t = 8000)]
+    port: u16,
+
+    /// How many requests can wait for the model, including those the worker holds for its next batches,
+    /// more are answered with 503
+    #[arg(long = "queue-size", default_value_t = 64, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    queue_size: usize,
+
+    /// How many queued requests with the same sampling params are generated together
+    #[arg(long = "parallel", default_value_t = 4, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    parallel: usize,
+
+    /// The most tokens a request may generate, the requests asking for more are answered with 400
+    #[arg(long = "max-request-tokens", default_value_t = 1024, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_request_tokens: usize,
+
+    #[command(flatten)]
+    context_policy: ContextPolicyArgs,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    /// the sampling params of the requests without "sampling"
+    #[command(flatten)]
+    sampling: SamplingArgs,
+}
+
+/// body of `POST /generate`
+#[derive(Debug, Deserialize)]
+struct GenerateRequest {
+    context: String,
+    task_type: TaskType,
+    /// the server's sampling params if missing, the missing fields take the `SamplingParams` defaults
+    sampling: Option<SamplingParams>,
+}
+
+/// the most stop strings of a request, each at most `MAX_STOP_LEN` bytes, every generated token is checked against them
+const MAX_STOP_STRINGS: usize = 16;
+const MAX_STOP_LEN: usize = 64;
+
+/// body of `POST /parse`
+#[derive(Debug, Deserialize)]
+struct ParseRequest {
+    /// the prompt + generated string
+    completion: String,
+}
+
+/// a `/generate` request waiting for the model
+struct Job {
+    prompt: String,
+    params: SamplingParams,
+    reply: oneshot::Sender<Result<ParsedTask, GenerateError>>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    queue: SyncSender<Job>,
+    /// the requests waiting for the model, in the queue or held by the worker for its next batches
+    queued: Arc<AtomicUsize>,
+    /// the bound of `queued`, more requests are answered with 503
+    queue_size: usize,
+    /// the bound of the `max_tokens` of a request, the server's own `sampling` is not bounded
+    max_request_tokens: usize,
+    sampling: SamplingParams,
+    context_policy: ContextPolicy,
+}
+
+pub async fn run(args: ServeArgs) -> Result<()> {
+    let sampling = args.sampling.sampling_params()?;
+    let config = args.backend.resolve().await?;
+
+    let (queue, jobs) = std::sync::mpsc::sync_channel(args.queue_size);
+    let queued = Arc::new(AtomicUsize::new(0));
+
+    // the generator lives in its own thread, a llama_context can't be shared between threads
+    let (ready, loaded) = std::sync::mpsc::channel::<Result<()>>();
+    let worker_queued = queued.clone();
+    let parallel = args.parallel;
+    let context_policy = args.context_policy.policy;
+    std::thread::spawn(move || {
+        let on_error = ready.clone();
+        let result = config.with_generator(parallel, |generator| {
+            let _ = ready.send(Ok(()));
+            worker(generator, jobs, parallel, context_policy, &worker_queued);
+            Ok(())
+        });
+        if let Err(err) = result {
+            let _ = on_error.send(Err(err));
+        }
+    });
+    loaded
+        .recv()
+        .map_err(|_| anyhow!("the generator thread exited"))??;
+
+    let app = router(AppState {
+        queue,
+        queued,
+        queue_size: args.queue_size,
+        max_request_tokens: args.max_request_tokens,
+        sampling,
+        context_policy,
+    });
+    let listener = tokio::net::TcpListener::bind((args.host.as_str(), args.port)).await?;
+    eprintln!("listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/generate", post(generate))
+        .route("/parse", post(parse))
+        .with_state(state)
+}
+
+/// generates the queued jobs until the server stops, in batches of up to `parallel` jobs with the same params,
+/// a job stops counting in `queued` once its batch starts
+/// a job with a seed is generated alone, the prompt `i` of a batch samples with `seed + i` so a seeded request
+/// would depend on the requests it is batched with
+fn worker(
+    mut generator: impl Generator,
+    jobs: Receiver<Job>,
+    parallel: usize,
+    context_policy: ContextPolicy,
+    queued: &AtomicUsize,
+) {
+    let mut pending: VecDeque<Job> = VecDeque::new();
+    loop {
+        let first = match pending.pop_front() {
+            Some(job) => job,
+            None => match jobs.recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            },
+        };
+        while pending.len() < parallel {
+            match jobs.try_recv() {
+                Ok(job) => pending.push_back(job),
+                Err(_) => break,
+            }
+        }
+
+        let mut batch = vec![first];
+        let mut i = 0;
+        while batch[0].params.seed.is_none() && batch.len() < parallel && i < pending.len() {
+            if pending[i].params == batch[0].params {
+                batch.extend(pending.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        queued.fetch_sub(batch.len(), Ordering::SeqCst);
+
+        let prompts: Vec<&str> = batch.iter().map(|job| job.prompt.as_str()).collect();
+        let results: Vec<Result<ParsedTask, GenerateError>> =
+            match generator.generate_batch(&prompts, &batch[0].params) {
+                Ok(completions) => completions
+                    .into_iter()
+                    .map(|completion| parse_generated_with(completion, context_policy))
+                    .collect(),
+                Err(err) => batch.iter().map(|_| Err(err.clone())).collect(),
+            };
+
+        for (job, result) in batch.into_iter().zip(results) {
+            // the client may be gone
+            let _ = job.reply.send(result);
+        }
+    }
+}
+
+async fn health(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(json!({
+        "status": "ok",
+        "queued": state.queued.load(Ordering::SeqCst),
+    }))
+}
+
+async fn generate(State(state): State<AppState>, Json(request): Json<GenerateRequest>) -> Response {
+    let prompt =
+        match prepare_prompt_with(&request.context, &request.task_type, state.context_policy) {
+            Ok(prompt) => prompt,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, &err.to_string(), None),
+        };
+    let params = match request.sampling {
+        Some(params) => match check_params(&params, state.max_request_tokens) {
+            Ok(()) => params,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, &err, None),
+        },
+        None => state.sampling.clone(),
+    };
+    let (reply, result) = oneshot::channel();
+    let job = Job {
+        prompt,
+        params,
+        reply,
+    };
+
+    // the worker holds jobs outside of the channel, the bound is checked on the count of every waiting job
+    if state.queued.fetch_add(1, Ordering::SeqCst) >= state.queue_size {
+        state.queued.fetch_sub(1, Ordering::SeqCst);
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "the generation queue is full",
+            None,
+        );
+    }
+    if let Err(err) = state.queue.try_send(job) {
+        state.queued.fetch_sub(1, Ordering::SeqCst);
+        let status = match err {
+            TrySendError::Full(_) => StatusCode::SERVICE_UNAVAILABLE,
+            TrySendError::Disconnected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        return error_response(status, "the generation queue is full or stopped", None);
+    }
+
+    match result.await {
+        Ok(Ok(task)) => Json(task).into_response(),
+        Ok(Err(GenerateError::Parse { completion, error })) => error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &error.to_string(),
+            Some(completion),
+        ),
+        Ok(Err(err @ GenerateError::ContextOverflow { .. })) => {
+            error_response(StatusCode::BAD_REQUEST, &err.to_string(), None)
+        }
+        Ok(Err(err)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string(), None),
+        Err(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the generator thread exited",
+            None,
+        ),
+    }
+}
+
+async fn parse(State(state): State<AppState>, Json(request): Json<ParseRequest>) -> Response {
+    match parse_task_with(&request.completion, state.context_policy) {
+        Ok(task) => Json(task).into_response(),
+        Err(err) => error_response(StatusCode::UNPROCESSABLE_ENTITY, &err.to_string(), None),
+    }
+}
+
+/// checks the sampling params of a request, a request can't hold the worker for more than `max_request_tokens` tokens
+fn check_params(params: &SamplingParams, max_request_tokens: usize) -> Result<(), String> {
+    if params.max_tokens > max_request_tokens {
+        return Err(format!(
+            "max_tokens {} is above the limit of the server {}",
+            params.max_tokens, max_request_tokens
+        ));
+    }
+    if params.stop.len() > MAX_STOP_STRINGS {
+        return Err(format!("more than {} stop strings", MAX_STOP_STRINGS));
+    }
+    if params.stop.iter().any(|stop| stop.len() > MAX_STOP_LEN) {
+        return Err(format!(
+            "a stop string is longer than {} bytes",
+            MAX_STOP_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// `{"error": ..., "completion": ...}`, the completion is set if it was generated but could not be parsed
+fn error_response(status: StatusCode, error: &str, completion: Option<String>) -> Response {
+    let mut body = json!({ "error": error });
+    if let Some(completion) = completion {
+        body["completion"] = json!(completion);
+    }
+    (status, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bonito::generator::{Completion, FinishReason, MockGenerator};
+    use bonito::prepare_prompt;
+
+    /// serves the router with a mock generator, returns the base URL
+    async fn serve_mock(responses: &[&str], queue_size: usize) -> String {
+        serve_generator(MockGenerator::new(responses.iter().copied()), queue_size).await
+    }
+
+    /// serves the router with the generator, returns the base URL
+    async fn serve_generator(
+        generator: impl Generator + Send + 'static,
+        queue_size: usize,
+    ) -> String {
+        let (queue, jobs) = std::sync::mpsc::sync_channel(queue_size);
+        let queued = Arc::new(AtomicUsize::new(0));
+        let worker_queued = queued.clone();
+        std::thread::spawn(move || {
+            worker(generator, jobs, 4, ContextPolicy::Reject, &worker_queued)
+        });
+
+        let app = router(AppState {
+            queue,
+            queued,
+            queue_size,
+            max_request_tokens: 1024,
+            sampling: SamplingParams::default(),
+            context_policy: ContextPolicy::Reject,
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base_url
+    }
+
+    /// posts the body, returns the status and the response, as a JSON string if it isn't JSON
+    async fn post_json(url: String, body: serde_json::Value) -> (u16, serde_json::Value) {
+        tokio::task::spawn_blocking(move || {
+            let response = match ureq::post(&url).send_json(body) {
+                Ok(response) => response,
+                Err(ureq::Error::Status(_, response)) => response,
+                Err(err) => panic!("{}", err),
+            };
+            let status = response.status();
+            let body = response.into_string().unwrap();
+            let body = serde_json::from_str(&body).unwrap_or(serde_json::Value::String(body));
+            (status, body)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let base_url = serve_mock(
+            &["{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis"],
+            8,
+        )
+        .await;
+
+        let (status, body) = post_json(
+            format!("{}/generate", base_url),
+            json!({"context": "Paris is the capital of France.", "task_type": "exqa", "sampling": {"seed": 1}}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body["task_type"], "exqa");
+        assert_eq!(body["answer"]["text"], "Paris");
+
+        // concurrent requests share the generator
+        let requests: Vec<_> = ["Paris is big.", "Rome is old.", "Paris is old."]
+            .iter()
+            .map(|context| {
+                tokio::spawn(post_json(
+                    format!("{}/generate", base_url),
+                    json!({"context": context, "task_type": "exqa"}),
+                ))
+            })
+            .collect();
+        let mut statuses = vec![];
+        for request in requests {
+            let (status, body) = request.await.unwrap();
+            if status == 422 {
+                assert_eq!(body["error"], "the answer is not found in the context");
+                assert!(body["completion"]
+                    .as_str()
+                    .unwrap()
+                    .contains("Rome is old."));
+            }
+            statuses.push(status);
+        }
+        assert_eq!(statuses, vec![200, 422, 200]);
+
+        let (status, body) = post_json(
+            format!("{}/generate", base_url),
+            json!({"context": "Paris", "task_type": "exqa", "sampling": {"max_tokens": 4096}}),
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(
+            body["error"],
+            "max_tokens 4096 is above the limit of the server 1024"
+        );
+
+        let (status, _) = post_json(
+            format!("{}/generate", base_url),
+            json!({"context": "Paris", "task_type": "unknown"}),
+        )
+        .await;
+        assert_eq!(status, 422);
+
+        let (status, body) = post_json(
+            format!("{}/generate", base_url),
+            json!({"context": "Paris <|pipe|>", "task_type": "exqa"}),
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(
+            body["error"],
+            "the context contains the reserved string <|pipe|>"
+        );
+
+        let (status, body) = post_json(
+            format!("{}/parse", base_url),
+            json!({"completion": format!("{}{}", prepare_prompt("Paris is big.", &TaskType::ExtractiveQuestionAnswering), "{{context}}\nQuestion: Where is the capital?\n<|pipe|>\nParis")}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body["question"], "Where is the capital?");
+
+        let (status, body) = post_json(
+            format!("{}/parse", base_url),
+            json!({"completion": "hello"}),
+        )
+        .await;
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "no <|tasktype|> found");
+
+        let health: serde_json::Value = tokio::task::spawn_blocking(move || {
+            ureq::get(&format!("{}/health", base_url))
+                .call()
+                .unwrap()
+                .into_json()
+                .unwrap()
+        })
+        .await
+        .unwrap();
+        assert_eq!(health, json!({"status": "ok", "queued": 0}));
+    }
+
+    #[tokio::test]
+    async fn test_serve_queue_full() {
+        // the bound counts the jobs of the channel and those held by the worker
+        let base_url = serve_mock(&["{{context}}\nWhere?\n<|pipe|>\nParis"], 0).await;
+        let (status, body) = post_json(
+            format!("{}/generate", base_url),
+            json!({"context": "Paris is big.", "task_type": "exqa"}),
+        )
+        .await;
+        assert_eq!(status, 503);
+        assert_eq!(body["error"], "the generation queue is full");
+    }
+
+    /// asks the question "Seed <seed>?" about Paris, slowly so the requests queue up
+    struct SeedGenerator;
+
+    impl Generator for SeedGenerator {
+        fn generate_batch(
+            &mut self,
+            prompts: &[&str],
+            params: &SamplingParams,
+        ) -> Result<Vec<Completion>, GenerateError> {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            Ok(prompts
+                .iter()
+                .enumerate()
+                .map(|(i, prompt)| Completion {
+                    text: format!(
+                        "{}{{{{context}}}}\nQuestion: Seed {}?\n<|pipe|>\nParis",
+                        prompt,
+                        params.offset_seed(i).seed.unwrap_or_default()
+                    ),
+                    finish_reason: FinishReason::Eos,
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_seeded_requests() {
+        let base_url = serve_generator(SeedGenerator, 8).await;
+        // the requests queued while the first one is generated are not batched together
+        let requests: Vec<_> = (0..3)
+            .map(|_| {
+                tokio::spawn(post_json(
+                    format!("{}/generate", base_url),
+                    json!({"context": "Paris is big.", "task_type": "exqa", "sampling": {"seed": 1}}),
+                ))
+            })
+            .collect();
+        for request in requests {
+            let (status, body) = request.await.unwrap();
+            assert_eq!(status, 200);
+            assert_eq!(body["question"], "Seed 1?");
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+/// a command line of the args of a subcommand
+#[derive(clap::Parser)]
+struct TestCli<A: clap::Args> {
+    #[command(flatten)]
+    args: A,
+}
+
+/// parses the args of a subcommand from its flags, e.g. `["--input", "corpus.txt"]`
+pub fn parse_args<A, I, T>(flags: I) -> A
+where
+    A: clap::Args,
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let flags = flags.into_iter().map(Into::into);
+    TestCli::<A>::parse_from(std::iter::once(OsString::from("bonitox")).chain(flags)).args
+}
+
+/// a directory for the files of a test, removed with its files when dropped
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// `name` tells apart the tests running at the same time
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("bonitox-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// the path of a file of the directory
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// reads back the records of a JSONL file
+pub fn read_records(path: &Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
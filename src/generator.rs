@@ -0,0 +1,496 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::sampling::SamplingParams;
+use crate::sanitize::ContextPolicy;
+use crate::stop::StopCriteria;
+use crate::utf8::Utf8Decoder;
+use crate::{parse_task_with, prepare_prompt, ParseError, ParsedTask, TaskType};
+
+/// error of loading a backend or generating a task
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenerateError {
+    /// the model or the backend could not be loaded
+    Load(String),
+    /// the prompts and the new tokens don't fit in the context window
+    ContextOverflow { required: usize, n_ctx: usize },
+    /// the backend failed to generate the completions
+    Backend(String),
+    /// the completion was generated but could not be parsed
+    Parse {
+        completion: String,
+        error: ParseError,
+    },
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::Load(err) => write!(f, "unable to load the model: {}", err),
+            GenerateError::ContextOverflow { required, n_ctx } => write!(
+                f,
+                "the prompts need {} tokens of KV cache but n_ctx is {}, reduce the number of prompts, the max tokens or the length of the context",
+                required, n_ctx
+            ),
+            GenerateError::Backend(err) => write!(f, "generation failed: {}", err),
+            GenerateError::Parse { error, .. } => {
+                write!(f, "failed to parse the completion: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/// why the generation of a completion stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// the model generated the end of sequence token
+    Eos,
+    /// a stop string or the end of the answer line of `StopCriteria`
+    Stop,
+    /// `max_tokens` were generated
+    Length,
+}
+
+impl std::fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FinishReason::Eos => write!(f, "eos"),
+            FinishReason::Stop => write!(f, "stop"),
+            FinishReason::Length => write!(f, "length"),
+        }
+    }
+}
+
+/// a completion generated from a prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// the prompt + generated string
+    pub text: String,
+    pub finish_reason: FinishReason,
+}
+
+/// a piece of the text generated for a prompt, handed out while the generation runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delta<'a> {
+    /// the index of the prompt in the batch
+    pub index: usize,
+    /// the attempt of `retry::generate_parsed_streaming`, always 0 from a `Generator`
+    pub attempt: usize,
+    pub text: &'a str,
+}
+
+/// a backend generating the completions of the prompts of `prepare_prompt`
+pub trait Generator {
+    /// generates the completions of the prompts, returns the completion of each prompt in order,
+    /// the generation stops at the `StopCriteria` of the prompt and the params
+    /// with a seed, the prompt `i` samples with `seed + i`, see `SamplingParams::offset_seed` across batches
+    fn generate_batch(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+    ) -> Result<Vec<Completion>, GenerateError>;
+
+    /// `generate_batch` calling `on_delta` with the pieces of the generated texts in the order they are generated,
+    /// the pieces of a prompt make up its generated text, without the text cut by the `StopCriteria`
+    /// by default the backend doesn't stream and the generated text of each prompt is one piece once done
+    fn generate_batch_streaming(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+        on_delta: &mut dyn FnMut(Delta),
+    ) -> Result<Vec<Completion>, GenerateError> {
+        let completions = self.generate_batch(prompts, params)?;
+        for (index, (prompt, completion)) in prompts.iter().zip(&completions).enumerate() {
+            on_delta(Delta {
+                index,
+                attempt: 0,
+                text: completion.text.get(prompt.len()..).unwrap_or_default(),
+            });
+        }
+        Ok(completions)
+    }
+
+    /// generates the completion of the prompt
+    fn generate(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<Completion, GenerateError> {
+        Ok(self.generate_batch(&[prompt], params)?.remove(0))
+    }
+
+    /// generates a task of `task_type` from the context
+    fn generate_task(
+        &mut self,
+        context: &str,
+        task_type: &TaskType,
+        params: &SamplingParams,
+    ) -> Result<ParsedTask, GenerateError> {
+        let prompt = prepare_prompt(context, task_type);
+        parse_generated(self.generate(&prompt, params)?)
+    }
+
+    /// `generate_task` calling `on_delta` with the pieces of the generated text as they are generated
+    fn generate_task_streaming(
+        &mut self,
+        context: &str,
+        task_type: &TaskType,
+        params: &SamplingParams,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<ParsedTask, GenerateError> {
+        let prompt = prepare_prompt(context, task_type);
+        let completion = self
+            .generate_batch_streaming(&[&prompt], params, &mut |delta| on_delta(delta.text))?
+            .remove(0);
+        parse_generated(completion)
+    }
+}
+
+impl<G: Generator + ?Sized> Generator for &mut G {
+    fn generate_batch(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+    ) -> Result<Vec<Completion>, GenerateError> {
+        (**self).generate_batch(prompts, params)
+    }
+
+    fn generate_batch_streaming(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+        on_delta: &mut dyn FnMut(Delta),
+    ) -> Result<Vec<Completion>, GenerateError> {
+        (**self).generate_batch_streaming(prompts, params, on_delta)
+    }
+}
+
+impl<G: Generator + ?Sized> Generator for Box<G> {
+    fn generate_batch(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+    ) -> Result<Vec<Completion>, GenerateError> {
+        (**self).generate_batch(prompts, params)
+    }
+
+    fn generate_batch_streaming(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+        on_delta: &mut dyn FnMut(Delta),
+    ) -> Result<Vec<Completion>, GenerateError> {
+        (**self).generate_batch_streaming(prompts, params, on_delta)
+    }
+}
+
+/// parses a generated completion, keeping the completion in the error
+pub fn parse_generated(completion: Completion) -> Result<ParsedTask, GenerateError> {
+    parse_generated_with(completion, ContextPolicy::default())
+}
+
+/// `parse_generated` of a prompt built by `prepare_prompt_with` and the policy
+pub fn parse_generated_with(
+    completion: Completion,
+    policy: ContextPolicy,
+) -> Result<ParsedTask, GenerateError> {
+    match parse_task_with(&completion.text, policy) {
+        Ok(mut task) => {
+            task.finish_reason = Some(completion.finish_reason);
+            task.completion_text = Some(completion.text);
+            Ok(task)
+        }
+        Err(error) => Err(GenerateError::Parse {
+            completion: completion.text,
+            error,
+        }),
+    }
+}
+
+/// generates a task of `task_type` from every context, lazily and `parallel` contexts per `generate_batch`,
+/// like `Bonito.generate_tasks` of python bonito the failed generations are returned as errors instead of dropped
+pub fn generate_tasks<'a, G, I>(
+    generator: G,
+    contexts: I,
+    task_type: &TaskType,
+    params: &'a SamplingParams,
+    parallel: usize,
+) -> GeneratedTasks<'a, G, I::IntoIter>
+where
+    G: Generator,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    GeneratedTasks {
+        generator,
+        contexts: contexts.into_iter(),
+        task_type: *task_type,
+        params,
+        parallel: parallel.max(1),
+        generated: VecDeque::new(),
+        n_generated: 0,
+    }
+}
+
+/// the tasks of `generate_tasks`, one per context in order
+pub struct GeneratedTasks<'a, G, I> {
+    generator: G,
+    contexts: I,
+    task_type: TaskType,
+    params: &'a SamplingParams,
+    parallel: usize,
+    generated: VecDeque<Result<ParsedTask, GenerateError>>,
+    /// the contexts generated so far, the seed offset of the next batch
+    n_generated: usize,
+}
+
+impl<G, I> Iterator for GeneratedTasks<'_, G, I>
+where
+    G: Generator,
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    type Item = Result<ParsedTask, GenerateError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated.is_empty() {
+            let prompts: Vec<String> = self
+                .contexts
+                .by_ref()
+                .take(self.parallel)
+                .map(|context| prepare_prompt(context.as_ref(), &self.task_type))
+                .collect();
+            if prompts.is_empty() {
+                return None;
+            }
+            let prompts: Vec<&str> = prompts.iter().map(String::as_str).collect();
+            let params = self.params.offset_seed(self.n_generated);
+            self.n_generated += prompts.len();
+            match self.generator.generate_batch(&prompts, &params) {
+                Ok(completions) => self
+                    .generated
+                    .extend(completions.into_iter().map(parse_generated)),
+                // every context of the batch fails with the same error
+                Err(err) => self
+                    .generated
+                    .extend(prompts.iter().map(|_| Err(err.clone()))),
+            }
+        }
+        self.generated.pop_front()
+    }
+}
+
+/// the bytes of a token of `MockGenerator`, the multi-byte characters are split across tokens like a byte-level
+/// tokenizer would
+const MOCK_TOKEN_LEN: usize = 2;
+
+/// a backend for tests, completes the prompts with canned generated strings in turn
+/// e.g. "{{context}}\nWhat is it?\n<|pipe|>\nParis", generated token by token and cut at the `StopCriteria`
+/// or after `max_tokens` like a model would stop
+#[derive(Debug, Clone, Default)]
+pub struct MockGenerator {
+    responses: Vec<String>,
+    next: usize,
+    /// the prompts received so far
+    pub prompts: Vec<String>,
+    /// the seed each prompt would have sampled with, `seed + i` for the prompt `i` of a batch
+    pub seeds: Vec<Option<u64>>,
+}
+
+impl MockGenerator {
+    pub fn new<I, S>(responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            responses: responses.into_iter().map(Into::into).collect(),
+            next: 0,
+            prompts: vec![],
+            seeds: vec![],
+        }
+    }
+}
+
+impl Generator for MockGenerator {
+    fn generate_batch(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+    ) -> Result<Vec<Completion>, GenerateError> {
+        self.generate_batch_streaming(prompts, params, &mut |_| {})
+    }
+
+    /// streams the tokens of the prompts one prompt after the other
+    fn generate_batch_streaming(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+        on_delta: &mut dyn FnMut(Delta),
+    ) -> Result<Vec<Completion>, GenerateError> {
+        if self.responses.is_empty() {
+            return Err(GenerateError::Backend("no mock responses".to_string()));
+        }
+        Ok(prompts
+            .iter()
+            .enumerate()
+            .map(|(index, prompt)| {
+                self.prompts.push(prompt.to_string());
+                self.seeds
+                    .push(params.seed.map(|seed| seed.wrapping_add(index as u64)));
+                let response = &self.responses[self.next % self.responses.len()];
+                self.next += 1;
+                let stop_criteria = StopCriteria::new(prompt, params);
+
+                let mut decoder = Utf8Decoder::new();
+                let mut generated = String::new();
+                let mut n_streamed = 0;
+                let mut finish_reason = FinishReason::Eos;
+                for (n_generated, token) in response.as_bytes().chunks(MOCK_TOKEN_LEN).enumerate() {
+                    if n_generated >= params.max_tokens {
+                        finish_reason = FinishReason::Length;
+                        break;
+                    }
+                    let new_str = decoder.push(token);
+                    generated.push_str(&new_str);
+                    if let Some(end) = stop_criteria.find_stop(&generated, new_str.len()) {
+                        generated.truncate(end);
+                        finish_reason = FinishReason::Stop;
+                        break;
+                    }
+                    let settled = stop_criteria.settled_len(&generated);
+                    if settled > n_streamed {
+                        on_delta(Delta {
+                            index,
+                            attempt: 0,
+                            text: &generated[n_streamed..settled],
+                        });
+                        n_streamed = settled;
+                    }
+                }
+                if finish_reason != FinishReason::Stop {
+                    generated.push_str(&decoder.finish());
+                }
+                if generated.len() > n_streamed {
+                    on_delta(Delta {
+                        index,
+                        attempt: 0,
+                        text: &generated[n_streamed..],
+                    });
+                }
+                Completion {
+                    text: format!("{}{}", prompt, generated),
+                    finish_reason,
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TaskFields;
+
+    #[test]
+    fn test_generate_tasks() {
+        let mut generator = MockGenerator::new([
+            "{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis",
+            "Refer to the passage below and answer the following question:\n\nPassage: {{context}}\n\nQuestion: What is the capital of Italy?\n<|pipe|>\nRome",
+        ]);
+        let contexts = [
+            "Paris is the capital of France.",
+            "Rome is the capital of Italy.",
+            "Bern is the capital of Switzerland.",
+        ];
+        let params = SamplingParams::default();
+
+        let tasks: Vec<_> = generate_tasks(
+            &mut generator,
+            contexts,
+            &TaskType::ExtractiveQuestionAnswering,
+            &params,
+            2,
+        )
+        .collect();
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(generator.prompts.len(), 3);
+        assert!(generator.prompts[2].contains("Bern"));
+
+        let task = tasks[1].as_ref().unwrap();
+        assert_eq!(task.completion.context, "Rome is the capital of Italy.");
+        match &task.fields {
+            TaskFields::ExtractiveQuestionAnswering { question, answer } => {
+                assert_eq!(question, "What is the capital of Italy?");
+                assert_eq!(answer.text, "Rome");
+            }
+            fields => panic!("unexpected fields {:?}", fields),
+        }
+
+        // the mock answers "Paris" about Bern, which is not in the context
+        match &tasks[2] {
+            Err(GenerateError::Parse { completion, error }) => {
+                assert!(completion.ends_with("<|pipe|>\nParis"));
+                assert_eq!(error, &ParseError::UngroundedAnswer);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        assert_eq!(
+            MockGenerator::default().generate("prompt", &params),
+            Err(GenerateError::Backend("no mock responses".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_generate_stop_criteria() {
+        let params = SamplingParams::default();
+
+        // the answer line of a short answer task ends the generation
+        let task = MockGenerator::new(["{{context}}\nWhere?\n<|pipe|>\nParis\n\nQ: and Rome?"])
+            .generate_task(
+                "Paris is the capital of France.",
+                &TaskType::ExtractiveQuestionAnswering,
+                &params,
+            )
+            .unwrap();
+        assert_eq!(task.completion.response, "Paris");
+        assert_eq!(task.finish_reason, Some(FinishReason::Stop));
+    }
+
+    #[test]
+    fn test_generate_utf8() {
+        let params = SamplingParams::default();
+
+        // the characters split across tokens are assembled before parsing
+        let context = "東京は日本の首都です。Café 🦀";
+        let task = MockGenerator::new(["{{context}}\n日本の首都は?\n<|pipe|>\n東京"])
+            .generate_task(context, &TaskType::ExtractiveQuestionAnswering, &params)
+            .unwrap();
+        assert_eq!(task.completion.context, context);
+        match &task.fields {
+            TaskFields::ExtractiveQuestionAnswering { question, answer } => {
+                assert_eq!(question, "{{context}}\n日本の首都は?");
+                assert_eq!(answer.text, "東京");
+                assert_eq!(answer.char_start, 0);
+            }
+            fields => panic!("unexpected fields {:?}", fields),
+        }
+
+        // max_tokens counts the byte tokens of the mock, not the characters
+        let completion = MockGenerator::new(["{{context}}\nWhere?\n<|pipe|>\nParis"])
+            .generate(
+                "prompt",
+                &SamplingParams {
+                    max_tokens: 3,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(completion.text, "prompt{{cont");
+        assert_eq!(completion.finish_reason, FinishReason::Length);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::labels::default_labels;
+use crate::stop::is_short_answer;
+use crate::{prompt_task_type, TaskType};
+
+/// the rules shared by every task type, text without special tokens: a run of `<` is never followed by `|`,
+/// a text may end with a run of `<`
+const COMMON_RULES: &str = r#"root ::= instruction "<|pipe|>" response
+instruction ::= space* (visible text* "<"* | "<"+)
+text ::= [^<] | "<"+ [^|<]
+line ::= [^\n<] | "<"+ [^\n|<]
+visible ::= [^ \t\r\n<] | "<"+ [^ \t\r\n|<]
+space ::= [ \t\r\n]
+"#;
+
+/// the GBNF grammar of the text generated after a prompt of `task_type`, for llama.cpp constrained decoding
+/// the text is a non-blank instruction, exactly one `<|pipe|>` and a non-blank response, then EOS
+/// - the task types with `default_labels` (yes-no, paraphrase, sentiment) answer with one of these labels,
+///   the options listed by a generated instruction are then answered with their text, not their letter
+/// - the other short answer task types of `is_short_answer` answer on a single line, their options or labels
+///   are listed by the generated instruction so they can't be known when the grammar is built
+/// - the others answer with any text
+pub fn task_grammar(task_type: &TaskType) -> String {
+    let response = match default_labels(task_type) {
+        Some(labels) => label_response(labels),
+        None if is_short_answer(task_type) => {
+            "response ::= space* (visible line* \"<\"* | \"<\"+)\n".to_string()
+        }
+        None => "response ::= space* (visible text* \"<\"* | \"<\"+)\n".to_string(),
+    };
+    format!("{}{}", COMMON_RULES, response)
+}
+
+/// the grammar of the task type of a prompt of `prepare_prompt`, any response if it's not a bonito prompt
+pub fn prompt_grammar(prompt: &str) -> String {
+    match prompt_task_type(prompt) {
+        Some(task_type) => task_grammar(&task_type),
+        None => task_grammar(&TaskType::Summarization),
+    }
+}
+
+/// a response which is one of the labels, optionally capitalized and followed by a period
+fn label_response(labels: &[&str]) -> String {
+    let alternatives: Vec<String> = labels
+        .iter()
+        .map(|label| label_alternative(label))
+        .collect();
+    format!(
+        "response ::= space* label \".\"?\nlabel ::= {}\n",
+        alternatives.join(" | ")
+    )
+}
+
+/// the GBNF of a lowercase label, e.g. `[yY] "es"` for "yes"
+fn label_alternative(label: &str) -> String {
+    let mut chars = label.chars();
+    let first = chars.next().unwrap_or_default();
+    format!(
+        "[{}{}] \"{}\"",
+        first,
+        first.to_ascii_uppercase(),
+        chars.as_str()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prepare_prompt;
+
+    #[test]
+    fn test_task_grammar() {
+        let ynqa = task_grammar(&TaskType::YesNoQuestionAnswering);
+        assert!(ynqa.starts_with("root ::= instruction \"<|pipe|>\" response\n"));
+        assert!(ynqa
+            .ends_with("response ::= space* label \".\"?\nlabel ::= [yY] \"es\" | [nN] \"o\"\n"));
+        assert_eq!(ynqa, task_grammar(&TaskType::ParaphraseIdentification));
+        assert!(task_grammar(&TaskType::Sentiment)
+            .ends_with("label ::= [pP] \"ositive\" | [nN] \"egative\"\n"));
+
+        // the options of nli and mcqa are listed by the generated instruction
+        let exqa = task_grammar(&TaskType::ExtractiveQuestionAnswering);
+        assert!(exqa.ends_with("response ::= space* (visible line* \"<\"* | \"<\"+)\n"));
+        assert_eq!(exqa, task_grammar(&TaskType::NaturalLanguageInference));
+        assert!(task_grammar(&TaskType::Summarization)
+            .ends_with("response ::= space* (visible text* \"<\"* | \"<\"+)\n"));
+        // "a << b" and a trailing "<" are allowed, only "<|" is not
+        assert!(ynqa.contains("text ::= [^<] | \"<\"+ [^|<]\n"));
+
+        let prompt = prepare_prompt("Paris.", &TaskType::Sentiment);
+        assert_eq!(prompt_grammar(&prompt), task_grammar(&TaskType::Sentiment));
+        assert_eq!(
+            prompt_grammar("not a prompt"),
+            task_grammar(&TaskType::Summarization)
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ParseError;
+
+/// where an extractive answer is found in the context, offsets are like SQuAD's `answer_start`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnswerSpan {
+    /// the answer as it is written in the context
+    pub text: String,
+    /// byte offset of the first byte of the answer in the context
+    pub byte_start: usize,
+    /// byte offset just after the answer in the context
+    pub byte_end: usize,
+    /// char offset of the first char of the answer in the context (SQuAD's `answer_start`)
+    pub char_start: usize,
+    /// char offset just after the answer in the context
+    pub char_end: usize,
+    /// true if the answer was found verbatim, false if only found ignoring case, whitespace and punctuation
+    pub exact: bool,
+}
+
+/// locates the answer in the context, first verbatim and then ignoring case, whitespace and punctuation,
+/// the answer must not start or end inside a word of the context, e.g. "art" is not found in "started"
+/// `ParseError::UngroundedAnswer` if the answer is not a span of the context
+pub fn locate_answer(context: &str, answer: &str) -> Result<AnswerSpan, ParseError> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Err(ParseError::EmptyAnswer);
+    }
+
+    let is_exact_match = |i: usize| {
+        let end = i + answer.len();
+        context[i..].starts_with(answer)
+            && is_word_boundary(
+                context[..i].chars().next_back(),
+                context[i..].chars().next(),
+            )
+            && is_word_boundary(
+                context[..end].chars().next_back(),
+                context[end..].chars().next(),
+            )
+    };
+    if let Some(byte_start) = context
+        .char_indices()
+        .map(|(i, _)| i)
+        .find(|&i| is_exact_match(i))
+    {
+        return Ok(span(context, byte_start, byte_start + answer.len(), true));
+    }
+
+    let (context_chars, offsets) = normalize_with_offsets(context);
+    let (answer_chars, _) = normalize_with_offsets(answer);
+    if answer_chars.is_empty() {
+        return Err(ParseError::UngroundedAnswer);
+    }
+    let n = answer_chars.len();
+    let i = (0..=context_chars.len().saturating_sub(n))
+        .find(|&i| {
+            context_chars[i..].starts_with(&answer_chars)
+                && is_word_boundary(
+                    i.checked_sub(1).map(|j| context_chars[j]),
+                    Some(context_chars[i]),
+                )
+                && is_word_boundary(
+                    Some(context_chars[i + n - 1]),
+                    context_chars.get(i + n).copied(),
+                )
+        })
+        .ok_or(ParseError::UngroundedAnswer)?;
+
+    let (byte_start, _) = offsets[i];
+    let (_, byte_end) = offsets[i + n - 1];
+    Ok(span(context, byte_start, byte_end, false))
+}
+
+/// lowercases the text and turns each run of whitespace and punctuation into one space, the words
+/// are then separated by exactly one space, returns the chars and the byte range each char comes from
+/// in the text
+fn normalize_with_offsets(text: &str) -> (Vec<char>, Vec<(usize, usize)>) {
+    let mut chars = vec![];
+    let mut offsets = vec![];
+    let mut pending_space = false;
+    for (i, c) in text.char_indices() {
+        if !c.is_alphanumeric() {
+            pending_space = true;
+        } else {
+            if pending_space && !chars.is_empty() {
+                chars.push(' ');
+                offsets.push((i, i));
+            }
+            pending_space = false;
+            for lower in c.to_lowercase() {
+                chars.push(lower);
+                offsets.push((i, i + c.len_utf8()));
+            }
+        }
+    }
+    (chars, offsets)
+}
+
+/// false if a word is split between the two chars
+fn is_word_boundary(before: Option<char>, after: Option<char>) -> bool {
+    !matches!((before, after), (Some(b), Some(a)) if is_word_char(b) && is_word_char(a))
+}
+
+/// the alphanumeric chars except those of the scripts written without spaces between the words
+/// (chinese, japanese, thai) where a word may start at any char
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+        && !matches!(
+            c,
+            '\u{0E00}'..='\u{0E7F}'
+                | '\u{3040}'..='\u{30FF}'
+                | '\u{3400}'..='\u{4DBF}'
+                | '\u{4E00}'..='\u{9FFF}'
+                | '\u{F900}'..='\u{FAFF}'
+                | '\u{FF66}'..='\u{FF9F}'
+                | '\u{20000}'..='\u{2FFFF}'
+        )
+}
+
+fn span(context: &str, byte_start: usize, byte_end: usize, exact: bool) -> AnswerSpan {
+    let char_start = context[..byte_start].chars().count();
+    let text = context[byte_start..byte_end].to_string();
+    AnswerSpan {
+        char_end: char_start + text.chars().count(),
+        text,
+        byte_start,
+        byte_end,
+        char_start,
+        exact,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_answer() {
+        let context =
+            "Café Müller opened in 1921 in Zürich. It was sold to the  Rossi family in 1950.";
+
+        let span = locate_answer(context, "1921").unwrap();
+        assert_eq!(span.text, "1921");
+        assert_eq!((span.byte_start, span.byte_end), (24, 28));
+        assert_eq!((span.char_start, span.char_end), (22, 26));
+        assert!(span.exact);
+
+        // case, whitespace and punctuation are ignored
+        let span = locate_answer(context, "the Rossi family.").unwrap();
+        assert_eq!(span.text, "the  Rossi family");
+        assert_eq!(&context[span.byte_start..span.byte_end], span.text);
+        assert_eq!(
+            context
+                .chars()
+                .skip(span.char_start)
+                .take(span.char_end - span.char_start)
+                .collect::<String>(),
+            span.text
+        );
+        assert!(!span.exact);
+
+        let span = locate_answer(context, "in zürich").unwrap();
+        assert_eq!(span.text, "in Zürich");
+        assert_eq!(span.char_start, 27);
+
+        assert_eq!(
+            locate_answer(context, "in Geneva"),
+            Err(ParseError::UngroundedAnswer)
+        );
+        assert_eq!(locate_answer(context, "  "), Err(ParseError::EmptyAnswer));
+
+        // punctuation separates words like whitespace
+        let span = locate_answer("Topic: Science/Tech news", "science tech").unwrap();
+        assert_eq!(span.text, "Science/Tech");
+        assert!(!span.exact);
+
+        // only whole words are matched
+        let span = locate_answer("He started the art fair", "art").unwrap();
+        assert_eq!((span.byte_start, span.byte_end), (15, 18));
+        assert!(span.exact);
+        let span = locate_answer("He started the Art fair", "art").unwrap();
+        assert_eq!(span.text, "Art");
+        assert_eq!(
+            locate_answer("He started the fair", "art"),
+            Err(ParseError::UngroundedAnswer)
+        );
+        // the words of chinese and japanese are not separated by spaces
+        let span = locate_answer("東京は日本の首都です。", "日本").unwrap();
+        assert_eq!(span.char_start, 3);
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+/// the huggingface repo of the bonito GGUF models
+pub const DEFAULT_MODEL_REPO: &str = "alexandreteles/bonito-v1-gguf";
+
+/// use k-quants because it's faster on metal https://github.com/ggerganov/llama.cpp/wiki/Feature-matrix
+pub const DEFAULT_MODEL_FILE: &str = "bonito-v1_q4_k_m.gguf";
+
+/// a GGUF model file in a huggingface repo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HubModel {
+    pub repo: String,
+    pub file: String,
+    /// branch, tag or commit
+    pub revision: String,
+}
+
+impl Default for HubModel {
+    fn default() -> Self {
+        Self {
+            repo: DEFAULT_MODEL_REPO.to_string(),
+            file: DEFAULT_MODEL_FILE.to_string(),
+            revision: "main".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for HubModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}@{})", self.file, self.repo, self.revision)
+    }
+}
+
+impl HubModel {
+    fn repo(&self) -> hf_hub::Repo {
+        hf_hub::Repo::with_revision(
+            self.repo.clone(),
+            hf_hub::RepoType::Model,
+            self.revision.clone(),
+        )
+    }
+
+    /// the path of the model in the local huggingface cache, None if it was never downloaded
+    pub fn cached_path(&self) -> Option<PathBuf> {
+        hf_hub::Cache::default().repo(self.repo()).get(&self.file)
+    }
+
+    /// downloads the model into the local huggingface cache if needed, returns its path
+    pub async fn download(&self, progress: bool) -> Result<PathBuf, hf_hub::api::tokio::ApiError> {
+        hf_hub::api::tokio::ApiBuilder::new()
+            .with_progress(progress)
+            .build()?
+            .repo(self.repo())
+            .get(&self.file)
+            .await
+    }
+}
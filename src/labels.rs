@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mcqa::{extract_options, match_option, normalize};
+use crate::{ParseError, ParsedCompletion, TaskType};
+
+/// other ways the model answers with a label, compared after `normalize`
+const SYNONYMS: [(&str, &[&str]); 5] = [
+    ("yes", &["true", "correct", "right", "yeah", "yep"]),
+    ("no", &["false", "incorrect", "wrong", "nope"]),
+    ("positive", &["pos", "good", "great"]),
+    ("negative", &["neg", "bad", "terrible"]),
+    ("neutral", &["mixed", "neither"]),
+];
+
+/// words that flip the meaning of an answer, e.g. "it is not positive"
+const NEGATIONS: [&str; 4] = ["not", "isn", "t", "never"];
+
+/// a classification answer mapped into a closed label set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassLabel {
+    /// the label set, from the options listed in the instruction or the default of the task type
+    pub labels: Vec<String>,
+    /// the index of the label matching the answer
+    pub index: usize,
+    /// the label matching the answer
+    pub label: String,
+    /// the answer after `<|pipe|>` as generated
+    pub raw_label: String,
+}
+
+/// the label set of a classification task type when the instruction doesn't list any options
+/// None for task types without a fixed label set (e.g. "topic classification")
+pub fn default_labels(task_type: &TaskType) -> Option<&'static [&'static str]> {
+    match task_type {
+        TaskType::YesNoQuestionAnswering => Some(&["yes", "no"]),
+        TaskType::ParaphraseIdentification => Some(&["yes", "no"]),
+        TaskType::Sentiment => Some(&["positive", "negative"]),
+        _ => None,
+    }
+}
+
+/// maps the answer of a classification completion ("yes-no question answering", "sentiment",
+/// "topic classification", "paraphrase identification") into the options listed in the instruction,
+/// or into `default_labels` if there are none
+/// `ParseError::UnknownLabel` if the answer doesn't match any label
+pub fn normalize_label(parsed: &ParsedCompletion) -> Result<ClassLabel, ParseError> {
+    let (labels, index) = match extract_options(&parsed.instruction) {
+        Some(choices) => {
+            let labels: Vec<String> = choices
+                .options
+                .iter()
+                .map(|option| option.text.clone())
+                .collect();
+            let index = match_option(&choices.options, &parsed.response)
+                .or_else(|| match_label(&labels, &parsed.response));
+            (labels, index)
+        }
+        None => {
+            let labels: Vec<String> = default_labels(&parsed.task_type)
+                .ok_or(ParseError::MissingOptions)?
+                .iter()
+                .map(|label| label.to_string())
+                .collect();
+            let index = match_label(&labels, &parsed.response);
+            (labels, index)
+        }
+    };
+    let index = index.ok_or_else(|| ParseError::UnknownLabel(parsed.response.clone()))?;
+
+    Ok(ClassLabel {
+        label: labels[index].clone(),
+        labels,
+        index,
+        raw_label: parsed.response.clone(),
+    })
+}
+
+/// the index of the label the answer refers to, tried in order:
+/// - the answer is the label or one of its synonyms ("Yes." for "yes")
+/// - the answer starts with the label ("yes, it is")
+/// - the answer mentions exactly one of the labels and no negation ("It's positive")
+fn match_label(labels: &[String], answer: &str) -> Option<usize> {
+    let answer = normalize(answer);
+    let keys: Vec<Vec<String>> = labels
+        .iter()
+        .map(|label| {
+            let label = normalize(label);
+            let synonyms = SYNONYMS
+                .iter()
+                .find(|(canonical, _)| *canonical == label)
+                .map(|(_, synonyms)| synonyms.iter().map(|synonym| synonym.to_string()));
+            let mut keys = vec![label];
+            keys.extend(synonyms.into_iter().flatten());
+            keys
+        })
+        .collect();
+
+    if let Some(i) = keys.iter().position(|keys| keys.contains(&answer)) {
+        return Some(i);
+    }
+
+    let starts_with = |key: &String| {
+        answer
+            .strip_prefix(key.as_str())
+            .is_some_and(|rest| rest.starts_with(' '))
+    };
+    // the longest label wins, e.g. "not duplicate" over "not"
+    if let Some((i, _)) = keys
+        .iter()
+        .enumerate()
+        .filter_map(|(i, keys)| {
+            let longest = keys
+                .iter()
+                .filter(|key| starts_with(key))
+                .map(String::len)
+                .max()?;
+            Some((i, longest))
+        })
+        .max_by_key(|&(_, len)| len)
+    {
+        return Some(i);
+    }
+
+    let words: Vec<&str> = answer.split(' ').collect();
+    if words.iter().any(|word| NEGATIONS.contains(word)) {
+        return None;
+    }
+    let mentions = |key: &String| {
+        let key: Vec<&str> = key.split(' ').collect();
+        words
+            .windows(key.len())
+            .any(|window| window == key.as_slice())
+    };
+    let mentioned: Vec<usize> = keys
+        .iter()
+        .enumerate()
+        .filter(|(_, keys)| keys.iter().any(mentions))
+        .map(|(i, _)| i)
+        .collect();
+    match mentioned.as_slice() {
+        [i] => Some(*i),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_completion;
+
+    const CONTEXT: &str =
+        "I bought these headphones last month and the sound is amazing for the price.";
+
+    #[test]
+    fn test_normalize_label() {
+        // default label set
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::YesNoQuestionAnswering,
+            "{{context}}\n\nIs the reviewer happy with the sound?\n<|pipe|>\nYes, it is.",
+        );
+        let label = normalize_label(&parsed).unwrap();
+        assert_eq!(label.labels, vec!["yes", "no"]);
+        assert_eq!(label.label, "yes");
+        assert_eq!(label.raw_label, "Yes, it is.");
+
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::Sentiment,
+            "{{context}}\nWhat is the sentiment of this review?\n<|pipe|>\nIt's positive",
+        );
+        assert_eq!(normalize_label(&parsed).unwrap().label, "positive");
+
+        // "Adopt" is not an options header, the default labels apply
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::Sentiment,
+            "{{context}}\nAdopt the following approach:\nrate the review\n<|pipe|>\npositive",
+        );
+        assert_eq!(normalize_label(&parsed).unwrap().label, "positive");
+
+        // labels from the options in the instruction
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::Sentiment,
+            "Review: {{context}}\nIs this review positive or negative?\nOPTIONS:\n- Negative\n- Positive\n<|pipe|>\npositive.",
+        );
+        let label = normalize_label(&parsed).unwrap();
+        assert_eq!(label.labels, vec!["Negative", "Positive"]);
+        assert_eq!(label.index, 1);
+        assert_eq!(label.label, "Positive");
+
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::TopicClassification,
+            "{{context}}\nWhich topic is this text about?\nA) World B) Sports C) Business D) Science/Tech\n<|pipe|>\nD",
+        );
+        assert_eq!(normalize_label(&parsed).unwrap().label, "Science/Tech");
+
+        // unmappable answers
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::Sentiment,
+            "{{context}}\nWhat is the sentiment of this review?\n<|pipe|>\nIt's not positive",
+        );
+        assert_eq!(
+            normalize_label(&parsed),
+            Err(ParseError::UnknownLabel("It's not positive".to_string()))
+        );
+
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::TopicClassification,
+            "{{context}}\nWhat is this text about?\n<|pipe|>\nheadphones",
+        );
+        assert_eq!(normalize_label(&parsed), Err(ParseError::MissingOptions));
+    }
+}
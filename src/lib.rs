@@ -1,6 +1,32 @@
+pub mod generator;
+pub mod grammar;
+pub mod grounding;
+#[cfg(feature = "hf-hub")]
+pub mod hub;
+pub mod labels;
+#[cfg(feature = "llama")]
+pub mod llama;
+pub mod mcqa;
+pub mod nli;
+#[cfg(feature = "openai")]
+pub mod openai;
+pub mod retry;
+pub mod sampling;
+pub mod sanitize;
+pub mod stop;
+pub mod stream;
+pub mod utf8;
 
+use grounding::{locate_answer, AnswerSpan};
+use labels::{normalize_label, ClassLabel};
+use mcqa::{parse_mcqa, MultipleChoice};
+use nli::{parse_nli, Inference};
+use sanitize::{desanitize, sanitize_context, ContextError, ContextPolicy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
 
 /// task types for bonito
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TaskType {
     ExtractiveQuestionAnswering,
     MultipleChoiceQuestionAnswering,
@@ -20,6 +46,26 @@ pub enum TaskType {
     NaturalLanguageInference,
 }
 
+/// all task types, in the order of the short task strings in `str_to_task_type`
+pub const TASK_TYPES: [TaskType; 16] = [
+    TaskType::ExtractiveQuestionAnswering,
+    TaskType::MultipleChoiceQuestionAnswering,
+    TaskType::QuestionGeneration,
+    TaskType::QuestionAnsweringWithoutChoices,
+    TaskType::YesNoQuestionAnswering,
+    TaskType::CoreferenceResolution,
+    TaskType::ParaphraseGeneration,
+    TaskType::ParaphraseIdentification,
+    TaskType::SentenceCompletion,
+    TaskType::Sentiment,
+    TaskType::Summarization,
+    TaskType::TextGeneration,
+    TaskType::TopicClassification,
+    TaskType::WordSenseDisambiguation,
+    TaskType::TextualEntailment,
+    TaskType::NaturalLanguageInference,
+];
+
 /// maps a short task string (e.g. "exqa") to a `TaskType`
 pub fn str_to_task_type(task_type_str: &str) -> Option<TaskType> {
     match task_type_str {
@@ -43,6 +89,44 @@ pub fn str_to_task_type(task_type_str: &str) -> Option<TaskType> {
     }
 }
 
+/// maps a `TaskType` to its short task string (e.g. "exqa"), the reverse of `str_to_task_type`
+pub fn task_type_to_str(task_type: &TaskType) -> &'static str {
+    match task_type {
+        TaskType::ExtractiveQuestionAnswering => "exqa",
+        TaskType::MultipleChoiceQuestionAnswering => "mcqa",
+        TaskType::QuestionGeneration => "qg",
+        TaskType::QuestionAnsweringWithoutChoices => "qa",
+        TaskType::YesNoQuestionAnswering => "ynqa",
+        TaskType::CoreferenceResolution => "coref",
+        TaskType::ParaphraseGeneration => "paraphrase",
+        TaskType::ParaphraseIdentification => "paraphrase_id",
+        TaskType::SentenceCompletion => "sent_comp",
+        TaskType::Sentiment => "sentiment",
+        TaskType::Summarization => "summarization",
+        TaskType::TextGeneration => "text_gen",
+        TaskType::TopicClassification => "topic_class",
+        TaskType::WordSenseDisambiguation => "wsd",
+        TaskType::TextualEntailment => "te",
+        TaskType::NaturalLanguageInference => "nli",
+    }
+}
+
+/// serialized as its short task string (e.g. "exqa")
+impl Serialize for TaskType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(task_type_to_str(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let task_type_str = String::deserialize(deserializer)?;
+        str_to_task_type(&task_type_str).ok_or_else(|| {
+            serde::de::Error::custom(format!("unknown task type \"{}\"", task_type_str))
+        })
+    }
+}
+
 /// maps a `TaskType` to a (long) string (e.g. "multiple-choice question answering") which is used in prompt
 /// ref https://github.com/BatsResearch/bonito/blob/main/bonito/model.py#L106C13-L106C27
 pub fn task_type_to_task_prompt(task_type: &TaskType) -> Option<String> {
@@ -70,6 +154,36 @@ pub fn task_type_to_task_prompt(task_type: &TaskType) -> Option<String> {
     }
 }
 
+/// maps a task prompt (e.g. "extractive question answering") back to a `TaskType`
+pub fn task_prompt_to_task_type(task_prompt: &str) -> Option<TaskType> {
+    match task_prompt {
+        "extractive question answering" => Some(TaskType::ExtractiveQuestionAnswering),
+        "multiple-choice question answering" => Some(TaskType::MultipleChoiceQuestionAnswering),
+        "question generation" => Some(TaskType::QuestionGeneration),
+        "question answering without choices" => Some(TaskType::QuestionAnsweringWithoutChoices),
+        "yes-no question answering" => Some(TaskType::YesNoQuestionAnswering),
+        "coreference resolution" => Some(TaskType::CoreferenceResolution),
+        "paraphrase generation" => Some(TaskType::ParaphraseGeneration),
+        "paraphrase identification" => Some(TaskType::ParaphraseIdentification),
+        "sentence completion" => Some(TaskType::SentenceCompletion),
+        "sentiment" => Some(TaskType::Sentiment),
+        "summarization" => Some(TaskType::Summarization),
+        "text generation" => Some(TaskType::TextGeneration),
+        "topic classification" => Some(TaskType::TopicClassification),
+        "word sense disambiguation" => Some(TaskType::WordSenseDisambiguation),
+        "textual entailment" => Some(TaskType::TextualEntailment),
+        "natural language inference" => Some(TaskType::NaturalLanguageInference),
+        _ => None,
+    }
+}
+
+/// the task type of a prompt of `prepare_prompt`, None if it's not a bonito prompt
+pub(crate) fn prompt_task_type(prompt: &str) -> Option<TaskType> {
+    let (_, after_task_type) = prompt.split_once("<|tasktype|>")?;
+    let (task_prompt, _) = after_task_type.split_once("<|context|>")?;
+    task_prompt_to_task_type(task_prompt.trim())
+}
+
 /// returns the prompt for the model based on the task type
 fn get_prompt_by_task_type(context: &str, task_prompt: &str) -> String {
     let mut prompt = String::from("<|tasktype|>\n");
@@ -80,264 +194,473 @@ fn get_prompt_by_task_type(context: &str, task_prompt: &str) -> String {
     prompt
 }
 
-/// parse the bonito LLM generated completion and return the question in string
-/// only works with prompt with "exqa"/"multiple-choice question answering"
-/// None if no question found
-pub fn parse_q(completion: &str, test_chunk: &str) -> Option<String> {
-    let pair: Vec<_> = completion.split("<|pipe|>").collect();
-    // no <|pipe|> found, return None
-    if pair.len() == 0 {
-        return None;
+/// a bonito LLM completion (prompt + generated text) split into its parts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParsedCompletion {
+    /// the task type found after `<|tasktype|>`
+    pub task_type: TaskType,
+    /// the context echoed between `<|context|>` and `<|task|>`
+    pub context: String,
+    /// the generated instruction, `{{context}}` is kept as a placeholder
+    pub instruction: String,
+    /// the generated instruction with `{{context}}` replaced by the context
+    pub rendered_instruction: String,
+    /// the generated response after `<|pipe|>`
+    pub response: String,
+}
+
+/// the special tokens bonito uses to delimit the prompt and the completion
+const SPECIAL_TOKENS: [&str; 4] = ["<|tasktype|>", "<|context|>", "<|task|>", "<|pipe|>"];
+
+/// errors returned when a bonito LLM completion can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// no `<|tasktype|>` found
+    MissingTaskTypeMarker,
+    /// the text after `<|tasktype|>` is not a known task prompt
+    UnknownTaskType(String),
+    /// no `<|context|>` found
+    MissingContextMarker,
+    /// no `<|task|>` found
+    MissingTaskMarker,
+    /// no `<|pipe|>` found
+    MissingPipe,
+    /// more than one `<|pipe|>` found
+    MultiplePipes,
+    /// the completion stops in the middle of a special token, most likely cut off by the token limit
+    TruncatedCompletion,
+    /// nothing left of the question/instruction after parsing
+    EmptyQuestion,
+    /// nothing after `<|pipe|>`
+    EmptyAnswer,
+    /// no answer choices found in the instruction
+    MissingOptions,
+    /// no hypothesis found in the instruction
+    MissingHypothesis,
+    /// the answer can't be mapped to any of the labels of the task
+    UnknownLabel(String),
+    /// the answer of an extractive task is not a span of the context
+    UngroundedAnswer,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MissingTaskTypeMarker => write!(f, "no <|tasktype|> found"),
+            ParseError::UnknownTaskType(task_prompt) => {
+                write!(f, "unknown task type \"{}\"", task_prompt)
+            }
+            ParseError::MissingContextMarker => write!(f, "no <|context|> found"),
+            ParseError::MissingTaskMarker => write!(f, "no <|task|> found"),
+            ParseError::MissingPipe => write!(f, "no <|pipe|> found"),
+            ParseError::MultiplePipes => write!(f, "more than one <|pipe|> found"),
+            ParseError::TruncatedCompletion => write!(f, "the completion is truncated"),
+            ParseError::EmptyQuestion => write!(f, "the question is empty"),
+            ParseError::EmptyAnswer => write!(f, "the answer is empty"),
+            ParseError::MissingOptions => write!(f, "no answer choices found"),
+            ParseError::MissingHypothesis => write!(f, "no hypothesis found"),
+            ParseError::UnknownLabel(label) => write!(f, "unknown label \"{}\"", label),
+            ParseError::UngroundedAnswer => write!(f, "the answer is not found in the context"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// the name of the error without its details, e.g. "unknown_label", to group the failures by reason
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::MissingTaskTypeMarker => "missing_task_type_marker",
+            ParseError::UnknownTaskType(_) => "unknown_task_type",
+            ParseError::MissingContextMarker => "missing_context_marker",
+            ParseError::MissingTaskMarker => "missing_task_marker",
+            ParseError::MissingPipe => "missing_pipe",
+            ParseError::MultiplePipes => "multiple_pipes",
+            ParseError::TruncatedCompletion => "truncated_completion",
+            ParseError::EmptyQuestion => "empty_question",
+            ParseError::EmptyAnswer => "empty_answer",
+            ParseError::MissingOptions => "missing_options",
+            ParseError::MissingHypothesis => "missing_hypothesis",
+            ParseError::UnknownLabel(_) => "unknown_label",
+            ParseError::UngroundedAnswer => "ungrounded_answer",
+        }
+    }
+}
+
+/// true if the text ends with the beginning of a special token (e.g. "<|pi")
+fn ends_with_partial_token(text: &str) -> bool {
+    SPECIAL_TOKENS
+        .iter()
+        .any(|token| (2..token.len()).any(|n| text.ends_with(&token[..n])))
+}
+
+/// returns `err` for a missing marker, or `TruncatedCompletion` if the completion was cut off
+fn missing_marker(completion: &str, err: ParseError) -> ParseError {
+    if ends_with_partial_token(completion) {
+        ParseError::TruncatedCompletion
+    } else {
+        err
+    }
+}
+
+/// splits the completion into the parts before and after the only `<|pipe|>`
+fn split_pipe(completion: &str) -> Result<(&str, &str), ParseError> {
+    let mut parts = completion.split("<|pipe|>");
+    let before_pipe = parts.next().unwrap_or_default();
+    match (parts.next(), parts.next()) {
+        (Some(after_pipe), None) => Ok((before_pipe, after_pipe)),
+        (Some(_), Some(_)) => Err(ParseError::MultiplePipes),
+        (None, _) => Err(missing_marker(completion, ParseError::MissingPipe)),
+    }
+}
+
+/// the part before the first `sep`, the whole text if there is no `sep`
+fn before<'a>(text: &'a str, sep: &str) -> &'a str {
+    text.split(sep).next().unwrap_or(text)
+}
+
+/// trims the text, None if nothing is left
+fn trimmed(text: &str) -> Option<String> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
     }
+}
 
-    let before_pipe = pair[0];
-    if before_pipe.contains("Q:") {
-        // try first to find "Q: A:"
-        // split the string by "Q:"
-        let pair_q: Vec<_> = before_pipe.split("Q:").collect();
-        // and take the second part
-        let after_q = pair_q[1];
+/// parse the bonito LLM generated completion into a `ParsedCompletion`
+/// works with all task types, the completion must contain the prompt built by `prepare_prompt`
+pub fn parse_completion(completion: &str) -> Result<ParsedCompletion, ParseError> {
+    parse_completion_with(completion, ContextPolicy::default())
+}
 
+/// `parse_completion` of a prompt built by `prepare_prompt_with` and the policy,
+/// the context, instruction and response are unescaped with `ContextPolicy::Escape`
+pub fn parse_completion_with(
+    completion: &str,
+    policy: ContextPolicy,
+) -> Result<ParsedCompletion, ParseError> {
+    let (_, after_task_type) = completion
+        .split_once("<|tasktype|>")
+        .ok_or_else(|| missing_marker(completion, ParseError::MissingTaskTypeMarker))?;
+    let (task_prompt, after_context) = after_task_type
+        .split_once("<|context|>")
+        .ok_or_else(|| missing_marker(completion, ParseError::MissingContextMarker))?;
+    let task_type = task_prompt_to_task_type(task_prompt.trim())
+        .ok_or_else(|| ParseError::UnknownTaskType(task_prompt.trim().to_string()))?;
+    let (context, after_task) = after_context
+        .split_once("<|task|>")
+        .ok_or_else(|| missing_marker(completion, ParseError::MissingTaskMarker))?;
+    let (instruction, response) = split_pipe(after_task)?;
+
+    let context = desanitize(context.trim(), policy).into_owned();
+    let instruction = trimmed(instruction).ok_or(ParseError::EmptyQuestion)?;
+    let instruction = desanitize(&instruction, policy).into_owned();
+    let rendered_instruction = instruction.replace("{{context}}", &context);
+    let response = trimmed(response).ok_or(ParseError::EmptyAnswer)?;
+    let response = desanitize(&response, policy).into_owned();
+
+    Ok(ParsedCompletion {
+        task_type,
+        context,
+        instruction,
+        rendered_instruction,
+        response,
+    })
+}
+
+/// parse the bonito LLM generated completion and return the question in string
+/// only works with prompt with "exqa"/"multiple-choice question answering"
+pub fn parse_q(completion: &str, test_chunk: &str) -> Result<String, ParseError> {
+    let (before_pipe, _) = split_pipe(completion)?;
+
+    // try first to find "Q: A:"
+    // split the string by "Q:" and take the second part
+    if let Some(after_q) = before_pipe.split("Q:").nth(1) {
         // remove and return if there is `Referring to the passage above, the correct answer to the given question is`
-        if after_q
-            .contains("Referring to the passage above, the correct answer to the given question is")
-        {
-            let pair_q: Vec<_> = after_q
-                .split(
-                    "Referring to the passage above, the correct answer to the given question is",
-                )
-                .collect();
-            let after_q = pair_q[0];
-            let trimmed = after_q.trim().to_string();
-            if trimmed.len() > 0 {
-                return Some(trimmed);
+        let referring =
+            "Referring to the passage above, the correct answer to the given question is";
+        if after_q.contains(referring) {
+            if let Some(q) = trimmed(before(after_q, referring)) {
+                return Ok(q);
             }
         }
 
         // remove and return if there is `A:`
         if after_q.contains("A:") {
-            // try to split the string by "A:"
-            let pair_a: Vec<_> = after_q.split("A:").collect();
-            // and take the first part
-            let after_a = pair_a[0];
+            // split the string by "A:" and take the first part
+            let after_a = before(after_q, "A:");
 
             // remove if there is `{{context}}`
             if after_a.contains("{{context}}") {
-                let pair_q: Vec<_> = after_q.split("{{context}}").collect();
-                let after_q = pair_q[0];
-                let trimmed = after_q.trim().to_string();
-                if trimmed.len() > 0 {
-                    return Some(trimmed);
+                if let Some(q) = trimmed(before(after_q, "{{context}}")) {
+                    return Ok(q);
                 }
             }
 
-            let trimmed = after_a.trim().to_string();
-            if trimmed.len() > 0 {
-                return Some(trimmed);
+            if let Some(q) = trimmed(after_a) {
+                return Ok(q);
             }
         }
     }
 
-    if before_pipe.contains("Question:") {
-        // split the string by "Q:"
-        let pair_question: Vec<_> = before_pipe.split("Question:").collect();
-        let after_question = pair_question[1];
-        let trimmed = after_question.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
+    if let Some(after_question) = before_pipe.split("Question:").nth(1) {
+        if let Some(q) = trimmed(after_question) {
+            return Ok(q);
         }
     }
 
-    if before_pipe.contains("What is the answer for the question:") {
-        let pair_q: Vec<_> = before_pipe
-            .split("What is the answer for the question:")
-            .collect();
-
-        // and take the second part
-        let after_q = pair_q[1];
-        // remove if there is `{{context}}`
-        if after_q.contains("{{context}}") {
-            let pair_q: Vec<_> = after_q.split("{{context}}").collect();
-            let after_q = pair_q[0];
-            let trimmed = after_q.trim().to_string();
-            if trimmed.len() > 0 {
-                return Some(trimmed);
+    for prefix in [
+        "What is the answer for the question:",
+        "answer the following question:",
+    ] {
+        if let Some(after_q) = before_pipe.split(prefix).nth(1) {
+            // remove if there is `{{context}}`
+            if after_q.contains("{{context}}") {
+                if let Some(q) = trimmed(before(after_q, "{{context}}")) {
+                    return Ok(q);
+                }
             }
-        }
 
-        let trimmed = after_q.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
-        }
-    }
-
-    if before_pipe.contains("answer the following question:") {
-        let pair_q: Vec<_> = before_pipe
-            .split("answer the following question:")
-            .collect();
-
-        // and take the second part
-        let after_q = pair_q[1];
-        // remove if there is `{{context}}`
-        if after_q.contains("{{context}}") {
-            let pair_q: Vec<_> = after_q.split("{{context}}").collect();
-            let after_q = pair_q[0];
-            let trimmed = after_q.trim().to_string();
-            if trimmed.len() > 0 {
-                return Some(trimmed);
+            if let Some(q) = trimmed(after_q) {
+                return Ok(q);
             }
         }
-
-        let trimmed = after_q.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
-        }
     }
 
-    if before_pipe
-        .contains("Given the paragraph above, please answer correctly the following question:")
+    if let Some(after_q) = before_pipe
+        .split("Given the paragraph above, please answer correctly the following question:")
+        .nth(1)
     {
-        let pair_q: Vec<_> = before_pipe
-            .split("Given the paragraph above, please answer correctly the following question:")
-            .collect();
-
-        // and take the second part
-        let after_q = pair_q[1];
         // remove if there is `Hint: {{context}}`
         if after_q.contains("Hint: {{context}}") {
-            let pair_q: Vec<_> = after_q.split("Hint: {{context}}").collect();
-            let after_q = pair_q[0];
-            let trimmed = after_q.trim().to_string();
-            if trimmed.len() > 0 {
-                return Some(trimmed);
+            if let Some(q) = trimmed(before(after_q, "Hint: {{context}}")) {
+                return Ok(q);
             }
         }
 
-        let trimmed = after_q.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
+        if let Some(q) = trimmed(after_q) {
+            return Ok(q);
         }
     }
 
     // no prefixes found, return whatever between "<|task|>" and "<|pipe|>"
-    let pair: Vec<_> = before_pipe.split("<|task|>").collect();
-    let after_task = pair[1];
+    let after_task = before_pipe
+        .split("<|task|>")
+        .nth(1)
+        .ok_or(ParseError::MissingTaskMarker)?;
 
     // remove if there is `Hint: {{context}}`
     if after_task.contains("Hint: {{context}}") {
-        let pair: Vec<_> = after_task.split("Hint: {{context}}").collect();
-        let after = pair[0];
-        let trimmed = after.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
+        if let Some(q) = trimmed(before(after_task, "Hint: {{context}}")) {
+            return Ok(q);
         }
     }
 
     // add the test_chunk if `Given the background: {{context}}`
-    if after_task.contains("Given the background: {{context}}") {
-        let pair: Vec<_> = after_task
-            .split("Given the background: {{context}}")
-            .collect();
-        let after_given_background = pair[1];
+    if let Some(after_given_background) =
+        after_task.split("Given the background: {{context}}").nth(1)
+    {
         let with_context = format!(
             "Given the background: {}\n{}",
             test_chunk, after_given_background
         );
-        let trimmed = with_context.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
+        if let Some(q) = trimmed(&with_context) {
+            return Ok(q);
         }
     }
 
     // add the context if `use this background: {{context}}`
     if after_task.contains("use this background: {{context}}") {
         let after_task = after_task.replace("{{context}}", test_chunk);
-        let trimmed = after_task.trim().to_string();
-        if trimmed.len() > 0 {
-            return Some(trimmed);
+        if let Some(q) = trimmed(&after_task) {
+            return Ok(q);
         }
     }
 
-    let trimmed = after_task.trim().to_string();
-    if trimmed.len() > 0 {
-        return Some(trimmed);
-    }
-
-    // if can't find "Q:" or ""
-    None
+    trimmed(after_task).ok_or(ParseError::EmptyQuestion)
 }
 
 /// parse the bonito LLM generated completion and return the answer in string
-/// only works with prompt with "exqa"/"multiple-choice question answering"
-/// None if no answer found
-pub fn parse_a(completion: &str) -> Option<String> {
-    let pair: Vec<_> = completion.split("<|pipe|>").collect();
+/// works with all task types
+pub fn parse_a(completion: &str) -> Result<String, ParseError> {
+    let (_, after_pipe) = split_pipe(completion)?;
+    trimmed(after_pipe).ok_or(ParseError::EmptyAnswer)
+}
 
-    if pair.len() > 0 {
-        return Some(pair[1].trim().to_string());
-    }
+/// task specific fields of a completion, parsed by the parser of its task type
+/// serialized without a tag, the fields of the variant are flattened into `ParsedTask`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TaskFields {
+    /// "extractive question answering", the answer located in the context
+    ExtractiveQuestionAnswering {
+        question: String,
+        answer: AnswerSpan,
+    },
+    /// "multiple-choice question answering"
+    MultipleChoice(MultipleChoice),
+    /// "natural language inference" and "textual entailment"
+    Inference(Inference),
+    /// "yes-no question answering", "sentiment", "topic classification" and "paraphrase identification"
+    Classification(ClassLabel),
+    /// task types without a dedicated parser, use the instruction and response of `ParsedCompletion`
+    Generic,
+}
 
-    return None;
+/// a completion parsed by `parse_task`, serialized as one flat record
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParsedTask {
+    #[serde(flatten)]
+    pub completion: ParsedCompletion,
+    #[serde(flatten)]
+    pub fields: TaskFields,
+    /// why the generation stopped, None if the completion was not generated by a `Generator`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<generator::FinishReason>,
+    /// the prompt + generated string, set with `finish_reason`, written as `completion` like the rejects
+    /// so that the generated tasks can be parsed again
+    #[serde(rename = "completion", skip_serializing_if = "Option::is_none")]
+    pub completion_text: Option<String>,
+    /// every attempt of `retry::generate_parsed` when retrying, the last one is the accepted one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<retry::Attempt>,
 }
 
-/// prepares the prompt for the model based on `TaskType`
-// ref https://github.com/BatsResearch/bonito/blob/main/bonito/model.py#L81
+/// parse the bonito LLM generated completion with `parse_completion` and the parser of its task type
+pub fn parse_task(completion: &str) -> Result<ParsedTask, ParseError> {
+    parse_task_with(completion, ContextPolicy::default())
+}
+
+/// `parse_task` of a prompt built by `prepare_prompt_with` and the policy
+pub fn parse_task_with(completion: &str, policy: ContextPolicy) -> Result<ParsedTask, ParseError> {
+    let parsed = parse_completion_with(completion, policy)?;
+    let fields = match parsed.task_type {
+        TaskType::ExtractiveQuestionAnswering => {
+            // parse_q may put the context in the question, the context as it's found in the completion so that
+            // the question is unescaped once
+            let raw_context = sanitize_context(&parsed.context, policy)
+                .unwrap_or(Cow::Borrowed(parsed.context.as_str()));
+            TaskFields::ExtractiveQuestionAnswering {
+                question: desanitize(&parse_q(completion, &raw_context)?, policy).into_owned(),
+                answer: locate_answer(&parsed.context, &parsed.response)?,
+            }
+        }
+        TaskType::MultipleChoiceQuestionAnswering => {
+            TaskFields::MultipleChoice(parse_mcqa(&parsed)?)
+        }
+        TaskType::NaturalLanguageInference | TaskType::TextualEntailment => {
+            TaskFields::Inference(parse_nli(&parsed)?)
+        }
+        TaskType::YesNoQuestionAnswering
+        | TaskType::Sentiment
+        | TaskType::TopicClassification
+        | TaskType::ParaphraseIdentification => {
+            TaskFields::Classification(normalize_label(&parsed)?)
+        }
+        _ => TaskFields::Generic,
+    };
+
+    Ok(ParsedTask {
+        completion: parsed,
+        fields,
+        finish_reason: None,
+        completion_text: None,
+        attempts: vec![],
+    })
+}
+
+/// prepares the prompt for the model based on `TaskType`,
+/// the special tokens of the context are escaped like `ContextPolicy::Escape`
 pub fn prepare_prompt(context: &str, task_type: &TaskType) -> String {
+    build_prompt(&sanitize::escape(context), task_type)
+}
+
+/// `prepare_prompt` with a policy for the special tokens of the context,
+/// `ContextError` if the policy is `ContextPolicy::Reject` and the context contains one
+pub fn prepare_prompt_with(
+    context: &str,
+    task_type: &TaskType,
+    policy: ContextPolicy,
+) -> Result<String, ContextError> {
+    Ok(build_prompt(&sanitize_context(context, policy)?, task_type))
+}
+
+// ref https://github.com/BatsResearch/bonito/blob/main/bonito/model.py#L81
+fn build_prompt(context: &str, task_type: &TaskType) -> String {
     match task_type {
         TaskType::ExtractiveQuestionAnswering => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::MultipleChoiceQuestionAnswering => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::QuestionGeneration => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::QuestionAnsweringWithoutChoices => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::YesNoQuestionAnswering => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::CoreferenceResolution => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::ParaphraseGeneration => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::ParaphraseIdentification => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::SentenceCompletion => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::Sentiment => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::Summarization => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::TextGeneration => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::TopicClassification => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::WordSenseDisambiguation => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::TextualEntailment => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
         TaskType::NaturalLanguageInference => {
-            get_prompt_by_task_type(&context, &task_type_to_task_prompt(task_type).unwrap())
+            get_prompt_by_task_type(context, &task_type_to_task_prompt(task_type).unwrap())
         }
     }
 }
 
+/// the parsed completion of the text generated after the prompt of the context, a fixture of the parser tests
+#[cfg(test)]
+pub(crate) fn parsed_completion(
+    context: &str,
+    task_type: &TaskType,
+    generated: &str,
+) -> ParsedCompletion {
+    parse_completion(&format!(
+        "{}{}",
+        prepare_prompt(context, task_type),
+        generated
+    ))
+    .unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     // covers the following cases:
     // - `Question: `
@@ -366,10 +689,10 @@ mod tests {
         1995"
         ;
         assert_eq!(
-            parse_q(&completion, "").unwrap(),
+            parse_q(completion, "").unwrap(),
             "What was Mattingly's last year of eligibility?"
         );
-        assert_eq!(parse_a(&completion).unwrap(), "1995");
+        assert_eq!(parse_a(completion).unwrap(), "1995");
 
         let completion = "<|tasktype|>
         extractive question answering
@@ -382,8 +705,8 @@ mod tests {
         {{context}}
         <|pipe|>
         Don Mattingly";
-        assert_eq!(parse_q(&completion, "").unwrap(), "What is the full name of the person who set a record in 1987? from the following article ?");
-        assert_eq!(parse_a(&completion).unwrap(), "Don Mattingly");
+        assert_eq!(parse_q(completion, "").unwrap(), "What is the full name of the person who set a record in 1987? from the following article ?");
+        assert_eq!(parse_a(completion).unwrap(), "Don Mattingly");
 
         let completion = 
             "<|tasktype|>
@@ -400,10 +723,10 @@ mod tests {
         <|pipe|>
         minus 0.11 percent";
         assert_eq!(
-            parse_q(&completion, "").unwrap(),
+            parse_q(completion, "").unwrap(),
             "What is the yield on German two-year government bonds?"
         );
-        assert_eq!(parse_a(&completion).unwrap(), "minus 0.11 percent");
+        assert_eq!(parse_a(completion).unwrap(), "minus 0.11 percent");
 
         let completion = "<|tasktype|>
         extractive question answering
@@ -420,8 +743,8 @@ mod tests {
         What is the full name of the person who said that Ms. Quick's motives to lie may have included liability and an insurance payment?
         <|pipe|>
         Mr. Hale";
-        assert_eq!(parse_q(&completion, "").unwrap(), "What is the full name of the person who said that Ms. Quick's motives to lie may have included liability and an insurance payment?");
-        assert_eq!(parse_a(&completion).unwrap(), "Mr. Hale");
+        assert_eq!(parse_q(completion, "").unwrap(), "What is the full name of the person who said that Ms. Quick's motives to lie may have included liability and an insurance payment?");
+        assert_eq!(parse_a(completion).unwrap(), "Mr. Hale");
 
         let completion = "<|tasktype|>
         extractive question answering
@@ -437,9 +760,9 @@ mod tests {
         Hint: {{context}}
         <|pipe|>
         Hamas launched a surprise attack on southern Israel";
-        assert_eq!(parse_q(&completion, "").unwrap(), "Which happened first, Israel launched a massive aerial bombardment of the Gaza Strip or Hamas launched a surprise attack on southern Israel?");
+        assert_eq!(parse_q(completion, "").unwrap(), "Which happened first, Israel launched a massive aerial bombardment of the Gaza Strip or Hamas launched a surprise attack on southern Israel?");
         assert_eq!(
-            parse_a(&completion).unwrap(),
+            parse_a(completion).unwrap(),
             "Hamas launched a surprise attack on southern Israel"
         );
 
@@ -455,10 +778,10 @@ mod tests {
         Hint: {{context}}
         <|pipe|>
         The Alhambra";
-        assert_eq!(parse_q(&completion, "").unwrap(), "20 students from the University of Chicago visited Granada as an study trip. They stayed in the Albaicin neighborhood. The other group of 20 students visited the medieval monuments in the city. The last group of 20 students visited the Alhambra.
+        assert_eq!(parse_q(completion, "").unwrap(), "20 students from the University of Chicago visited Granada as an study trip. They stayed in the Albaicin neighborhood. The other group of 20 students visited the medieval monuments in the city. The last group of 20 students visited the Alhambra.
         
         Which group learned more about Renaissance architecture?");
-        assert_eq!(parse_a(&completion).unwrap(), "The Alhambra");
+        assert_eq!(parse_a(completion).unwrap(), "The Alhambra");
 
         let completion = "<|tasktype|>
         extractive question answering
@@ -476,8 +799,8 @@ mod tests {
         Answer the following question: Which instance would not have ephemeral storage, instance A or instance B?
         <|pipe|>
         instance B";
-        assert_eq!(parse_q(&completion, "Filesystems in the Kubernetes container provide ephemeral storage, by default. This means that a restart of the pod will wipe out any data on such containers, and therefore, this form of storage is quite limiting in anything but trivial applications. A Kubernetes volume[60] provides persistent storage that exists for the lifetime of the pod itself. This storage can also be used as shared disk space for containers within the pod. Volumes are mounted at specific mount points within the container, which are defined by the pod configuration, and cannot mount onto other volumes or link to other volumes. The same volume can be mounted at different points in the file system tree by different containers.").unwrap(), "Given the background: Filesystems in the Kubernetes container provide ephemeral storage, by default. This means that a restart of the pod will wipe out any data on such containers, and therefore, this form of storage is quite limiting in anything but trivial applications. A Kubernetes volume[60] provides persistent storage that exists for the lifetime of the pod itself. This storage can also be used as shared disk space for containers within the pod. Volumes are mounted at specific mount points within the container, which are defined by the pod configuration, and cannot mount onto other volumes or link to other volumes. The same volume can be mounted at different points in the file system tree by different containers.\n\n        \n        and the situation: John is a cloud engineer. He wanted to try out Kubernetes. To that end, he created two instances, instance A and instance B. Instance A is running on Kubernetes container. But instance B is not running on Kubernetes container. There are some files in both instances. He needs to figure out how they are different.\n        \n        Answer the following question: Which instance would not have ephemeral storage, instance A or instance B?");
-        assert_eq!(parse_a(&completion).unwrap(), "instance B");
+        assert_eq!(parse_q(completion, "Filesystems in the Kubernetes container provide ephemeral storage, by default. This means that a restart of the pod will wipe out any data on such containers, and therefore, this form of storage is quite limiting in anything but trivial applications. A Kubernetes volume[60] provides persistent storage that exists for the lifetime of the pod itself. This storage can also be used as shared disk space for containers within the pod. Volumes are mounted at specific mount points within the container, which are defined by the pod configuration, and cannot mount onto other volumes or link to other volumes. The same volume can be mounted at different points in the file system tree by different containers.").unwrap(), "Given the background: Filesystems in the Kubernetes container provide ephemeral storage, by default. This means that a restart of the pod will wipe out any data on such containers, and therefore, this form of storage is quite limiting in anything but trivial applications. A Kubernetes volume[60] provides persistent storage that exists for the lifetime of the pod itself. This storage can also be used as shared disk space for containers within the pod. Volumes are mounted at specific mount points within the container, which are defined by the pod configuration, and cannot mount onto other volumes or link to other volumes. The same volume can be mounted at different points in the file system tree by different containers.\n\n        \n        and the situation: John is a cloud engineer. He wanted to try out Kubernetes. To that end, he created two instances, instance A and instance B. Instance A is running on Kubernetes container. But instance B is not running on Kubernetes container. There are some files in both instances. He needs to figure out how they are different.\n        \n        Answer the following question: Which instance would not have ephemeral storage, instance A or instance B?");
+        assert_eq!(parse_a(completion).unwrap(), "instance B");
 
         let completion = "<|tasktype|>
         extractive question answering
@@ -491,7 +814,245 @@ mod tests {
         What is an answer for this question: Will adopting Kubernetes and DevOps help or hinder John's team in improving their product?
         <|pipe|>
         help";
-        assert_eq!(parse_q(&completion, "By doing so, your organization fosters transparency and accountability across all parties involved, thereby minimizing potential conflicts downstream. 2. Implement robust automation tools - Empower developers with self-service capabilities through automated workflows and platforms such as GitOps. Automated deployment pipelines reduce manual intervention, minimize human error, and enable faster iterations. Moreover, incorporating policy-as-code concepts ensures consistent enforcement of organizational standards throughout various stages of the application lifecycle. 3. Encourage knowledge sharing and cross-functional training - Facilitate regular interactions among team members via workshops, hackathons, lunch & learn sessions, or other collaborative initiatives. Cross-pollination of skills helps bridge gaps between different functions and enables better communication channels. Furthermore, empowering individuals to wear multiple hats bolsters understanding of interdependencies among diverse domains, leading to improved empathy and reduced friction points. 4. Measure what matters - Identify key performance indicators (KPIs) aligned with desired business outcomes. Monitor progress against these metrics regularly and adjust course accordingly. Examples include mean time to recovery (MTTR), change failure rate, lead time for changes, deployment frequency, and customer satisfaction indices. Quantifying achievements visibly demonstrates tangible value delivered through adopted methodologies and encourages continuous improvement efforts. 5. Foster a culture of experimentation and learning - Cultivate an environment where taking calculated risks is encouraged, and failures serve as opportunities for growth rather than sources of blame. Support bottom-up innovation efforts by providing psychological safety nets and celebrating small wins along the way. Embracing this mindset fuels curiosity, promotes creative problem solving, and ultimately leads to greater resiliency in navigating complex landscapes. Navigating the delicate dance between control and agility requires careful consideration of marketing and business strategies, particularly regarding internal communications and education efforts surrounding Kubernetes and DevOps adoption. Organizations able to strike this elusive balance stand to reap significant rewards in terms of enhanced efficiency, increased productivity, and sustainable competitive advantage.").unwrap(), "I have a new situation: John is a software developer who works for a multinational tech company. His team has been developing a new product for the past year. Although they have made great progress, there are still some issues with the product. John's team decided to adopt Kubernetes and DevOps practices to improve the product.\n        \n        But I can use this background: By doing so, your organization fosters transparency and accountability across all parties involved, thereby minimizing potential conflicts downstream. 2. Implement robust automation tools - Empower developers with self-service capabilities through automated workflows and platforms such as GitOps. Automated deployment pipelines reduce manual intervention, minimize human error, and enable faster iterations. Moreover, incorporating policy-as-code concepts ensures consistent enforcement of organizational standards throughout various stages of the application lifecycle. 3. Encourage knowledge sharing and cross-functional training - Facilitate regular interactions among team members via workshops, hackathons, lunch & learn sessions, or other collaborative initiatives. Cross-pollination of skills helps bridge gaps between different functions and enables better communication channels. Furthermore, empowering individuals to wear multiple hats bolsters understanding of interdependencies among diverse domains, leading to improved empathy and reduced friction points. 4. Measure what matters - Identify key performance indicators (KPIs) aligned with desired business outcomes. Monitor progress against these metrics regularly and adjust course accordingly. Examples include mean time to recovery (MTTR), change failure rate, lead time for changes, deployment frequency, and customer satisfaction indices. Quantifying achievements visibly demonstrates tangible value delivered through adopted methodologies and encourages continuous improvement efforts. 5. Foster a culture of experimentation and learning - Cultivate an environment where taking calculated risks is encouraged, and failures serve as opportunities for growth rather than sources of blame. Support bottom-up innovation efforts by providing psychological safety nets and celebrating small wins along the way. Embracing this mindset fuels curiosity, promotes creative problem solving, and ultimately leads to greater resiliency in navigating complex landscapes. Navigating the delicate dance between control and agility requires careful consideration of marketing and business strategies, particularly regarding internal communications and education efforts surrounding Kubernetes and DevOps adoption. Organizations able to strike this elusive balance stand to reap significant rewards in terms of enhanced efficiency, increased productivity, and sustainable competitive advantage.\n        \n        What is an answer for this question: Will adopting Kubernetes and DevOps help or hinder John's team in improving their product?");
-        assert_eq!(parse_a(&completion).unwrap(), "help");
+        assert_eq!(parse_q(completion, "By doing so, your organization fosters transparency and accountability across all parties involved, thereby minimizing potential conflicts downstream. 2. Implement robust automation tools - Empower developers with self-service capabilities through automated workflows and platforms such as GitOps. Automated deployment pipelines reduce manual intervention, minimize human error, and enable faster iterations. Moreover, incorporating policy-as-code concepts ensures consistent enforcement of organizational standards throughout various stages of the application lifecycle. 3. Encourage knowledge sharing and cross-functional training - Facilitate regular interactions among team members via workshops, hackathons, lunch & learn sessions, or other collaborative initiatives. Cross-pollination of skills helps bridge gaps between different functions and enables better communication channels. Furthermore, empowering individuals to wear multiple hats bolsters understanding of interdependencies among diverse domains, leading to improved empathy and reduced friction points. 4. Measure what matters - Identify key performance indicators (KPIs) aligned with desired business outcomes. Monitor progress against these metrics regularly and adjust course accordingly. Examples include mean time to recovery (MTTR), change failure rate, lead time for changes, deployment frequency, and customer satisfaction indices. Quantifying achievements visibly demonstrates tangible value delivered through adopted methodologies and encourages continuous improvement efforts. 5. Foster a culture of experimentation and learning - Cultivate an environment where taking calculated risks is encouraged, and failures serve as opportunities for growth rather than sources of blame. Support bottom-up innovation efforts by providing psychological safety nets and celebrating small wins along the way. Embracing this mindset fuels curiosity, promotes creative problem solving, and ultimately leads to greater resiliency in navigating complex landscapes. Navigating the delicate dance between control and agility requires careful consideration of marketing and business strategies, particularly regarding internal communications and education efforts surrounding Kubernetes and DevOps adoption. Organizations able to strike this elusive balance stand to reap significant rewards in terms of enhanced efficiency, increased productivity, and sustainable competitive advantage.").unwrap(), "I have a new situation: John is a software developer who works for a multinational tech company. His team has been developing a new product for the past year. Although they have made great progress, there are still some issues with the product. John's team decided to adopt Kubernetes and DevOps practices to improve the product.\n        \n        But I can use this background: By doing so, your organization fosters transparency and accountability across all parties involved, thereby minimizing potential conflicts downstream. 2. Implement robust automation tools - Empower developers with self-service capabilities through automated workflows and platforms such as GitOps. Automated deployment pipelines reduce manual intervention, minimize human error, and enable faster iterations. Moreover, incorporating policy-as-code concepts ensures consistent enforcement of organizational standards throughout various stages of the application lifecycle. 3. Encourage knowledge sharing and cross-functional training - Facilitate regular interactions among team members via workshops, hackathons, lunch & learn sessions, or other collaborative initiatives. Cross-pollination of skills helps bridge gaps between different functions and enables better communication channels. Furthermore, empowering individuals to wear multiple hats bolsters understanding of interdependencies among diverse domains, leading to improved empathy and reduced friction points. 4. Measure what matters - Identify key performance indicators (KPIs) aligned with desired business outcomes. Monitor progress against these metrics regularly and adjust course accordingly. Examples include mean time to recovery (MTTR), change failure rate, lead time for changes, deployment frequency, and customer satisfaction indices. Quantifying achievements visibly demonstrates tangible value delivered through adopted methodologies and encourages continuous improvement efforts. 5. Foster a culture of experimentation and learning - Cultivate an environment where taking calculated risks is encouraged, and failures serve as opportunities for growth rather than sources of blame. Support bottom-up innovation efforts by providing psychological safety nets and celebrating small wins along the way. Embracing this mindset fuels curiosity, promotes creative problem solving, and ultimately leads to greater resiliency in navigating complex landscapes. Navigating the delicate dance between control and agility requires careful consideration of marketing and business strategies, particularly regarding internal communications and education efforts surrounding Kubernetes and DevOps adoption. Organizations able to strike this elusive balance stand to reap significant rewards in terms of enhanced efficiency, increased productivity, and sustainable competitive advantage.\n        \n        What is an answer for this question: Will adopting Kubernetes and DevOps help or hinder John's team in improving their product?");
+        assert_eq!(parse_a(completion).unwrap(), "help");
+    }
+
+    #[test]
+    fn test_parse_completion() {
+        let context = "Mount Everest is Earth's highest mountain above sea level, located in the Mahalangur Himal sub-range of the Himalayas.";
+        let mut completion = prepare_prompt(context, &TaskType::ExtractiveQuestionAnswering);
+        completion.push_str("{{context}}\n\nQ: Where is Mount Everest located?\n<|pipe|>\nthe Mahalangur Himal sub-range of the Himalayas");
+
+        let parsed = parse_completion(&completion).unwrap();
+        assert_eq!(parsed.task_type, TaskType::ExtractiveQuestionAnswering);
+        assert_eq!(parsed.context, context);
+        assert_eq!(
+            parsed.instruction,
+            "{{context}}\n\nQ: Where is Mount Everest located?"
+        );
+        assert_eq!(
+            parsed.rendered_instruction,
+            format!("{}\n\nQ: Where is Mount Everest located?", context)
+        );
+        assert_eq!(
+            parsed.response,
+            "the Mahalangur Himal sub-range of the Himalayas"
+        );
+
+        // every task prompt maps back to its task type
+        let completion = format!(
+            "{}Is the premise true?\n<|pipe|>\nyes",
+            prepare_prompt(context, &TaskType::NaturalLanguageInference)
+        );
+        assert_eq!(
+            parse_completion(&completion).unwrap().task_type,
+            TaskType::NaturalLanguageInference
+        );
+
+        // no <|pipe|> found
+        let completion = prepare_prompt(context, &TaskType::Sentiment);
+        assert_eq!(parse_completion(&completion), Err(ParseError::MissingPipe));
+    }
+
+    #[test]
+    fn test_parse_task() {
+        let context = "Mount Everest is Earth's highest mountain above sea level, located in the Mahalangur Himal sub-range of the Himalayas.";
+        let completion = format!(
+            "{}{{{{context}}}}\nQ: Where is Mount Everest located?\nA:\n<|pipe|>\nthe mahalangur himal sub-range",
+            prepare_prompt(context, &TaskType::ExtractiveQuestionAnswering)
+        );
+        let parsed = parse_task(&completion).unwrap();
+        match parsed.fields {
+            TaskFields::ExtractiveQuestionAnswering { question, answer } => {
+                assert_eq!(question, "Where is Mount Everest located?");
+                assert_eq!(answer.text, "the Mahalangur Himal sub-range");
+                assert_eq!(answer.char_start, 70);
+                assert!(!answer.exact);
+            }
+            fields => panic!("unexpected fields {:?}", fields),
+        }
+
+        let completion = format!(
+            "{}{{{{context}}}}\nQ: Where is Mount Everest located?\nA:\n<|pipe|>\nin Nepal",
+            prepare_prompt(context, &TaskType::ExtractiveQuestionAnswering)
+        );
+        assert_eq!(parse_task(&completion), Err(ParseError::UngroundedAnswer));
+
+        let completion = format!(
+            "{}Summarize the text.\n{{{{context}}}}\n<|pipe|>\nMount Everest is the highest mountain.",
+            prepare_prompt(context, &TaskType::Summarization)
+        );
+        assert_eq!(parse_task(&completion).unwrap().fields, TaskFields::Generic);
+
+        let completion = format!(
+            "{}{{{{context}}}}\nDoes the text say where Mount Everest is?\n<|pipe|>\nYes.",
+            prepare_prompt(context, &TaskType::YesNoQuestionAnswering)
+        );
+        let record = serde_json::to_value(parse_task(&completion).unwrap()).unwrap();
+        assert_eq!(record["task_type"], "ynqa");
+        assert_eq!(record["response"], "Yes.");
+        assert_eq!(record["label"], "yes");
+        assert_eq!(record["labels"], serde_json::json!(["yes", "no"]));
+
+        for task_type in TASK_TYPES {
+            assert_eq!(
+                str_to_task_type(task_type_to_str(&task_type)),
+                Some(task_type)
+            );
+        }
+    }
+
+    #[test]
+    fn test_context_policy() {
+        // a context which is itself a bonito completion
+        let context = "Q: Where is {{context}}?\n<|pipe|>\nParis";
+        let task_type = TaskType::ExtractiveQuestionAnswering;
+        let generated = "{{context}}\nWhat is the answer?\n<|pipe|>\n<\\|pipe|>\nParis";
+
+        let completion = format!("{}{}", prepare_prompt(context, &task_type), generated);
+        let task = parse_task(&completion).unwrap();
+        assert_eq!(task.completion.context, context);
+        assert_eq!(task.completion.response, "<|pipe|>\nParis");
+        match &task.fields {
+            TaskFields::ExtractiveQuestionAnswering { answer, .. } => {
+                assert_eq!(answer.char_start, 25)
+            }
+            fields => panic!("unexpected fields {:?}", fields),
+        }
+
+        let prompt = prepare_prompt_with(context, &task_type, ContextPolicy::Strip).unwrap();
+        let completion = format!("{}{{{{context}}}}\nWhere?\n<|pipe|>\nParis", prompt);
+        let parsed = parse_completion_with(&completion, ContextPolicy::Strip).unwrap();
+        assert_eq!(parsed.context, "Q: Where is ?\n\nParis");
+
+        assert_eq!(
+            prepare_prompt_with(context, &task_type, ContextPolicy::Reject),
+            Err(ContextError {
+                reserved: "<|pipe|>"
+            })
+        );
+        // without escaping the <|pipe|> of the context breaks the parser
+        let completion = format!("{}{}", build_prompt(context, &task_type), generated);
+        assert_eq!(
+            parse_task(&completion).unwrap_err(),
+            ParseError::MultiplePipes
+        );
+
+        // the context put in the question is unescaped once, an escaped sequence of the context is kept
+        let context = "Paris, split on <|pipe|> or <\\|pipe|>.";
+        let generated = "Given the background: {{context}}\nWhere?\n<|pipe|>\nParis";
+        let completion = format!("{}{}", prepare_prompt(context, &task_type), generated);
+        match parse_task(&completion).unwrap().fields {
+            TaskFields::ExtractiveQuestionAnswering { question, .. } => assert_eq!(
+                question,
+                format!("Given the background: {}\n\nWhere?", context)
+            ),
+            fields => panic!("unexpected fields {:?}", fields),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let prompt = prepare_prompt("The sky is blue.", &TaskType::ExtractiveQuestionAnswering);
+
+        // cut off before <|pipe|> is complete
+        let completion = format!("{}Q: What color is the sky?\n<|pi", prompt);
+        assert_eq!(parse_a(&completion), Err(ParseError::TruncatedCompletion));
+        assert_eq!(
+            parse_q(&completion, "The sky is blue."),
+            Err(ParseError::TruncatedCompletion)
+        );
+        assert_eq!(
+            parse_completion(&completion),
+            Err(ParseError::TruncatedCompletion)
+        );
+
+        let completion = format!("{}Q: What color is the sky?", prompt);
+        assert_eq!(parse_a(&completion), Err(ParseError::MissingPipe));
+
+        let completion = format!("{}Q: What color is the sky?\n<|pipe|>\n", prompt);
+        assert_eq!(parse_a(&completion), Err(ParseError::EmptyAnswer));
+        assert_eq!(parse_completion(&completion), Err(ParseError::EmptyAnswer));
+
+        let completion = format!("{}\n<|pipe|>\nblue", prompt);
+        assert_eq!(
+            parse_q(&completion, "The sky is blue."),
+            Err(ParseError::EmptyQuestion)
+        );
+
+        let completion = format!(
+            "{}Q: What color is the sky?\n<|pipe|>\nblue<|pipe|>",
+            prompt
+        );
+        assert_eq!(parse_a(&completion), Err(ParseError::MultiplePipes));
+
+        assert_eq!(
+            parse_q("What color is the sky?<|pipe|>blue", "The sky is blue."),
+            Err(ParseError::MissingTaskMarker)
+        );
+        assert_eq!(
+            parse_completion("<|tasktype|>\nsorting\n<|context|>\n<|task|>\nQ<|pipe|>A"),
+            Err(ParseError::UnknownTaskType("sorting".to_string()))
+        );
+        assert_eq!(
+            ParseError::UnknownTaskType("sorting".to_string()).kind(),
+            "unknown_task_type"
+        );
+    }
+
+    /// builds completions out of fragments of bonito completions so that markers show up in odd places
+    fn completion_fragments() -> impl Strategy<Value = String> {
+        let fragment = prop_oneof![
+            Just("<|tasktype|>".to_string()),
+            Just("\nextractive question answering\n".to_string()),
+            Just("<|context|>".to_string()),
+            Just("<|task|>".to_string()),
+            Just("<|pipe|>".to_string()),
+            Just("<|".to_string()),
+            Just("{{context}}".to_string()),
+            Just("Q:".to_string()),
+            Just("A:".to_string()),
+            Just("Question:".to_string()),
+            Just("Given the background: {{context}}".to_string()),
+            Just("use this background: {{context}}".to_string()),
+            Just("Hint: {{context}}".to_string()),
+            ".{0,16}",
+        ];
+        prop::collection::vec(fragment, 0..12).prop_map(|fragments| fragments.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics_on_random_input(completion in ".*", test_chunk in ".*") {
+            let _ = parse_completion(&completion);
+            let _ = parse_q(&completion, &test_chunk);
+            let _ = parse_a(&completion);
+        }
+
+        #[test]
+        fn parse_never_panics_on_fragments(completion in completion_fragments()) {
+            let _ = parse_completion(&completion);
+            let _ = parse_q(&completion, "context");
+            let _ = parse_a(&completion);
+        }
+
+        #[test]
+        fn parse_never_panics_on_truncated_completion(cut in 0usize..1000) {
+            let mut completion = prepare_prompt("Bonito 🐟 generates tasks from unannotated text.", &TaskType::ExtractiveQuestionAnswering);
+            completion.push_str("{{context}}\nQ: What does Bonito generate?\nA:\n<|pipe|>\ntasks");
+            let cut = completion.char_indices().map(|(i, _)| i).nth(cut).unwrap_or(completion.len());
+            let truncated = &completion[..cut];
+
+            let parsed = parse_completion(truncated);
+            let q = parse_q(truncated, "Bonito 🐟 generates tasks from unannotated text.");
+            let a = parse_a(truncated);
+            // nothing can be parsed until the answer has started
+            if !truncated.contains("<|pipe|>") {
+                prop_assert!(parsed.is_err());
+                prop_assert!(q.is_err());
+                prop_assert!(a.is_err());
+            }
+        }
     }
 }
@@ -0,0 +1,480 @@
+use crate::generator::{
+    generate_tasks, Completion, Delta, FinishReason, GenerateError, GeneratedTasks, Generator,
+};
+use crate::grammar::prompt_grammar;
+use crate::sampling::{SamplingParams, TokenPicker};
+use crate::stop::StopCriteria;
+use crate::utf8::Utf8Decoder;
+use crate::{ParsedTask, TaskType};
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::LlamaModel;
+use llama_cpp_2::sampling::LlamaSampler;
+use llama_cpp_2::token::data_array::LlamaTokenDataArray;
+use llama_cpp_2::token::LlamaToken;
+use std::num::NonZeroU32;
+use std::path::Path;
+
+/// how many contexts `generate_tasks` generates in parallel by default
+pub const DEFAULT_PARALLEL: usize = 4;
+
+/// a prompt being generated as one sequence of a batch
+struct Sequence {
+    /// the index of the prompt in the batch
+    index: usize,
+    /// completion = prompt + generated string
+    completion: String,
+    /// the length of the prompt in `completion`
+    prompt_len: usize,
+    stop_criteria: StopCriteria,
+    /// set once the sequence is done
+    finish_reason: Option<FinishReason>,
+    /// the llama.cpp samplers narrowing the candidates, the grammar of the prompt with `SamplingParams::constrained`
+    /// and the repetition penalty follow the sampled tokens
+    sampler: LlamaSampler,
+    /// picks the token among the candidates left by `sampler`
+    picker: TokenPicker,
+    /// assembles the bytes of the tokens into `completion`
+    decoder: Utf8Decoder,
+    /// the length of the generated text handed out to `on_delta`
+    n_streamed: usize,
+    /// the sampled token to decode next, None once the sequence is done
+    next_token: Option<LlamaToken>,
+    /// position of `next_token` in the sequence
+    n_cur: i32,
+    n_generated: usize,
+    /// index in the last decoded batch of the logits to sample from
+    logits_index: i32,
+}
+
+impl Sequence {
+    /// hands out the generated text up to `end` which wasn't yet
+    fn stream_to(&mut self, end: usize, on_delta: &mut dyn FnMut(Delta)) {
+        if end > self.n_streamed {
+            on_delta(Delta {
+                index: self.index,
+                attempt: 0,
+                text: &self.completion[self.prompt_len + self.n_streamed..self.prompt_len + end],
+            });
+            self.n_streamed = end;
+        }
+    }
+}
+
+fn llama_error(err: impl std::fmt::Display) -> GenerateError {
+    GenerateError::Backend(err.to_string())
+}
+
+/// the chain of llama.cpp samplers of a prompt, the pick itself is left to `TokenPicker` which takes a 64 bits seed
+/// the grammar comes first so the masked tokens don't take the places of `top_k`
+/// `SamplingParams::tfs_z` is ignored, llama.cpp dropped tail free sampling
+fn prompt_sampler(
+    model: &LlamaModel,
+    prompt: &str,
+    params: &SamplingParams,
+) -> Result<LlamaSampler, GenerateError> {
+    let mut samplers = vec![];
+    if params.constrained {
+        samplers.push(
+            LlamaSampler::grammar(model, &prompt_grammar(prompt), "root").map_err(llama_error)?,
+        );
+    }
+    samplers.push(LlamaSampler::penalties(
+        model.n_vocab(),
+        i32::try_from(params.repeat_last_n).unwrap_or(i32::MAX),
+        params.repeat_penalty,
+        0.0,
+        0.0,
+    ));
+    samplers.push(LlamaSampler::top_k(params.top_k));
+    samplers.push(LlamaSampler::typical(params.typical_p, 1));
+    samplers.push(LlamaSampler::top_p(params.top_p, 1));
+    samplers.push(LlamaSampler::min_p(params.min_p, 1));
+    // the temperature is skipped when greedy, it would divide the logits by 0
+    if !params.is_greedy() {
+        samplers.push(LlamaSampler::temp(params.temperature));
+    }
+    Ok(LlamaSampler::chain_simple(samplers))
+}
+
+/// the unnormalized probabilities of the candidates from their logits, 0 for the tokens masked by the grammar
+fn probabilities(candidates: &LlamaTokenDataArray) -> Vec<f32> {
+    let max_logit = candidates
+        .data
+        .iter()
+        .map(|candidate| candidate.logit())
+        .fold(f32::NEG_INFINITY, f32::max);
+    candidates
+        .data
+        .iter()
+        .map(|candidate| (candidate.logit() - max_logit).exp())
+        .collect()
+}
+
+/// the vocabulary and the metadata of the bonito model, loaded without the weights to count the tokens
+/// of the prompts
+pub struct Vocab {
+    _llama_cpp_backend: LlamaBackend,
+    model: LlamaModel,
+    n_ctx_train: usize,
+}
+
+impl Vocab {
+    /// initializes llama.cpp and loads the vocabulary of the GGUF model
+    pub fn load(model_path: impl AsRef<Path>) -> Result<Self, GenerateError> {
+        let model_path = model_path.as_ref();
+        let model_params = LlamaModelParams::default().with_vocab_only(true);
+        let (llama_cpp_backend, model) = load_model(model_path, &model_params)?;
+
+        // the context window is a metadata of the architecture, e.g. `llama.context_length`
+        let metadata = |key: &str| {
+            model.meta_val_str(key).map_err(|err| {
+                GenerateError::Load(format!("{}: {}: {}", model_path.display(), key, err))
+            })
+        };
+        let architecture = metadata("general.architecture")?;
+        let context_length = format!("{}.context_length", architecture);
+        let n_ctx_train = metadata(&context_length)?.parse().map_err(|err| {
+            GenerateError::Load(format!(
+                "{}: {}: {}",
+                model_path.display(),
+                context_length,
+                err
+            ))
+        })?;
+
+        Ok(Self {
+            _llama_cpp_backend: llama_cpp_backend,
+            model,
+            n_ctx_train,
+        })
+    }
+
+    /// the context window the model was trained with
+    pub fn n_ctx_train(&self) -> usize {
+        self.n_ctx_train
+    }
+
+    /// the number of tokens of the text with the BOS token, as `Bonito::count_tokens`
+    pub fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(&self.model, text)
+    }
+}
+
+/// the bonito model loaded with llama.cpp, mirrors the `Bonito` class of python bonito
+/// ref https://github.com/BatsResearch/bonito?tab=readme-ov-file#basic-usage
+pub struct Bonito {
+    llama_cpp_backend: LlamaBackend,
+    model: LlamaModel,
+    parallel: usize,
+}
+
+/// initializes llama.cpp and loads the GGUF model
+fn load_model(
+    model_path: &Path,
+    model_params: &LlamaModelParams,
+) -> Result<(LlamaBackend, LlamaModel), GenerateError> {
+    // llama.cpp logging flag
+    let llama_cpp_log = false;
+
+    let mut llama_cpp_backend =
+        LlamaBackend::init().map_err(|err| GenerateError::Load(err.to_string()))?;
+
+    if !llama_cpp_log {
+        llama_cpp_backend.void_logs();
+    }
+
+    let model = LlamaModel::load_from_file(&llama_cpp_backend, model_path, model_params)
+        .map_err(|err| GenerateError::Load(format!("{}: {}", model_path.display(), err)))?;
+    Ok((llama_cpp_backend, model))
+}
+
+/// the number of tokens of the text with the BOS token, as a prompt is tokenized for generation
+fn count_tokens(model: &LlamaModel, text: &str) -> usize {
+    model.vocab().tokenize(text.as_bytes(), true, true).len()
+}
+
+impl Bonito {
+    /// initializes llama.cpp and loads the GGUF model
+    pub fn load(model_path: impl AsRef<Path>) -> Result<Self, GenerateError> {
+        let (llama_cpp_backend, model) =
+            load_model(model_path.as_ref(), &LlamaModelParams::default())?;
+
+        Ok(Self {
+            llama_cpp_backend,
+            model,
+            parallel: DEFAULT_PARALLEL,
+        })
+    }
+
+    /// how many contexts `generate_tasks` generates in parallel, as sequences of the same batch,
+    /// the most prompts a batch of `session` can hold
+    pub fn with_parallel(mut self, parallel: usize) -> Self {
+        self.parallel = parallel.max(1);
+        self
+    }
+
+    /// creates a session with a context as large as the context window the model was trained with,
+    /// with a sequence per prompt of a batch of up to `with_parallel` prompts,
+    /// reuse the session to avoid allocating the KV cache for every generation
+    pub fn session(&self) -> Result<Session<'_>, GenerateError> {
+        let ctx_params = LlamaContextParams::default()
+            .with_n_ctx(NonZeroU32::new(self.model.n_ctx_train()))
+            .with_n_seq_max(self.parallel as u32);
+
+        let ctx = self
+            .model
+            .new_context(&self.llama_cpp_backend, ctx_params)
+            .map_err(|err| GenerateError::Load(err.to_string()))?;
+
+        Ok(Session {
+            model: &self.model,
+            ctx,
+            n_seq_max: self.parallel,
+        })
+    }
+
+    /// the context window the model was trained with, the n_ctx of `session`
+    pub fn n_ctx_train(&self) -> usize {
+        self.model.n_ctx_train() as usize
+    }
+
+    /// the number of tokens of the text with the BOS token, as a prompt is tokenized for generation
+    pub fn count_tokens(&self, text: &str) -> Result<usize, GenerateError> {
+        Ok(count_tokens(&self.model, text))
+    }
+
+    /// generates a task of `task_type` from the context
+    pub fn generate_task(
+        &self,
+        context: &str,
+        task_type: &TaskType,
+        params: &SamplingParams,
+    ) -> Result<ParsedTask, GenerateError> {
+        self.session()?.generate_task(context, task_type, params)
+    }
+
+    /// `generator::generate_tasks` with a session of the model, `with_parallel` contexts at a time
+    pub fn generate_tasks<'a, I>(
+        &'a self,
+        contexts: I,
+        task_type: &TaskType,
+        params: &'a SamplingParams,
+    ) -> Result<GeneratedTasks<'a, Session<'a>, I::IntoIter>, GenerateError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Ok(generate_tasks(
+            self.session()?,
+            contexts,
+            task_type,
+            params,
+            self.parallel,
+        ))
+    }
+}
+
+/// a llama_context of a loaded `Bonito` model, generations reuse its KV cache
+pub struct Session<'a> {
+    model: &'a LlamaModel,
+    ctx: LlamaContext<'a>,
+    /// the sequences of the context, the most prompts of a batch
+    n_seq_max: usize,
+}
+
+impl Generator for Session<'_> {
+    /// generates the completions of the prompts in parallel, one sequence id per prompt in the same batch,
+    /// returns the completion of each prompt in order
+    /// with a seed, the sequence `i` samples with `seed + i`, so the same prompt repeated gives different samples
+    /// within a batch, the callers offset the seed of the next batches with `SamplingParams::offset_seed`
+    fn generate_batch(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+    ) -> Result<Vec<Completion>, GenerateError> {
+        self.generate_batch_streaming(prompts, params, &mut |_| {})
+    }
+
+    /// streams the tokens of every sequence as they are sampled
+    fn generate_batch_streaming(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+        on_delta: &mut dyn FnMut(Delta),
+    ) -> Result<Vec<Completion>, GenerateError> {
+        if prompts.len() > self.n_seq_max {
+            return Err(GenerateError::Backend(format!(
+                "{} prompts in a batch of at most {} sequences, see `Bonito::with_parallel`",
+                prompts.len(),
+                self.n_seq_max
+            )));
+        }
+        let model = self.model;
+        let vocab = model.vocab();
+        let ctx = &mut self.ctx;
+        let batch_size = 512;
+
+        // drop whatever a failed generation may have left in the KV cache
+        ctx.clear_kv_cache();
+
+        // tokenize the prompts, the special tokens of the prompt format (`<|tasktype|>`...) are parsed
+        let tokens_lists: Vec<Vec<LlamaToken>> = prompts
+            .iter()
+            .map(|prompt| vocab.tokenize(prompt.as_bytes(), true, true))
+            .collect();
+
+        // make sure the KV cache is big enough to hold the prompt and the new tokens of every sequence
+        let n_ctx = ctx.n_ctx() as usize;
+        let n_prompt_tokens: usize = tokens_lists.iter().map(Vec::len).sum();
+        let n_kv_req = n_prompt_tokens + prompts.len() * params.max_tokens;
+        if n_kv_req > n_ctx {
+            return Err(GenerateError::ContextOverflow {
+                required: n_kv_req,
+                n_ctx,
+            });
+        }
+
+        // create a llama_batch with size `batch_size`
+        // we use this object to submit token data for decoding
+        let mut batch = LlamaBatch::new(batch_size.max(prompts.len()), 1);
+
+        // samples the next token of a sequence from the logits of the last decoded batch,
+        // the sequence is done at the end of generation token, at its stop criteria or after `max_tokens`
+        let mut sample =
+            |ctx: &mut LlamaContext, sequence: &mut Sequence| -> Result<(), GenerateError> {
+                let mut candidates = ctx.token_data_array_ith(sequence.logits_index);
+                candidates.apply_sampler(&mut sequence.sampler);
+                let picked = sequence.picker.pick(&probabilities(&candidates));
+                let new_token_id = candidates.data[picked].id();
+
+                sequence.next_token = None;
+                let is_eog = vocab.is_eog(new_token_id);
+                if is_eog || sequence.n_generated >= params.max_tokens {
+                    sequence.finish_reason = Some(if is_eog {
+                        FinishReason::Eos
+                    } else {
+                        FinishReason::Length
+                    });
+                    // the bytes of a character the generation stopped in the middle of
+                    sequence.completion.push_str(&sequence.decoder.finish());
+                    sequence.stream_to(sequence.completion.len() - sequence.prompt_len, on_delta);
+                    return Ok(());
+                }
+
+                sequence.sampler.accept(new_token_id);
+                // a token can be a part of a multi-byte character, the bytes are assembled by the decoder
+                let new_bytes = vocab.token_to_piece(new_token_id, true, None);
+                let new_str = sequence.decoder.push(&new_bytes);
+                sequence.completion.push_str(&new_str);
+                sequence.n_generated += 1;
+
+                // the stop strings may span several tokens, they are looked for in the generated text
+                let generated = &sequence.completion[sequence.prompt_len..];
+                if let Some(end) = sequence.stop_criteria.find_stop(generated, new_str.len()) {
+                    sequence.completion.truncate(sequence.prompt_len + end);
+                    sequence.finish_reason = Some(FinishReason::Stop);
+                    sequence.stream_to(end, on_delta);
+                    return Ok(());
+                }
+                let settled = sequence.stop_criteria.settled_len(generated);
+                sequence.stream_to(settled, on_delta);
+                sequence.next_token = Some(new_token_id);
+                Ok(())
+            };
+
+        let mut sequences = Vec::with_capacity(prompts.len());
+        for (index, (prompt, tokens_list)) in prompts.iter().zip(&tokens_lists).enumerate() {
+            sequences.push(Sequence {
+                index,
+                completion: prompt.to_string(),
+                prompt_len: prompt.len(),
+                stop_criteria: StopCriteria::new(prompt, params),
+                finish_reason: None,
+                sampler: prompt_sampler(model, prompt, params)?,
+                picker: TokenPicker::new(&params.offset_seed(index)),
+                decoder: Utf8Decoder::new(),
+                n_streamed: 0,
+                next_token: None,
+                n_cur: tokens_list.len() as i32,
+                n_generated: 0,
+                logits_index: 0,
+            });
+        }
+
+        // decode the prompts together, in batches of up to `batch_size` tokens, a long prompt spans several batches
+        // and the KV cache keeps its previous tokens, a sequence samples its first token once the batch with
+        // the last token of its prompt is decoded
+        batch.clear();
+        let mut prefilled = vec![];
+        for (seq_id, tokens_list) in (0_i32..).zip(&tokens_lists) {
+            let n_prompt_tokens = tokens_list.len() as i32;
+            for (i, token) in (0_i32..).zip(tokens_list) {
+                if batch.n_tokens() as usize >= batch_size {
+                    ctx.decode(&mut batch).map_err(llama_error)?;
+                    for sequence in prefilled.drain(..) {
+                        sample(ctx, &mut sequences[sequence])?;
+                    }
+                    batch.clear();
+                }
+                // llama_decode will output logits only for the last token of the prompt
+                let is_last = i == n_prompt_tokens - 1;
+                batch
+                    .add(*token, i, &[seq_id], is_last)
+                    .map_err(llama_error)?;
+                if is_last {
+                    sequences[seq_id as usize].logits_index = batch.n_tokens() - 1;
+                    prefilled.push(seq_id as usize);
+                }
+            }
+        }
+        if batch.n_tokens() > 0 {
+            ctx.decode(&mut batch).map_err(llama_error)?;
+            for sequence in prefilled.drain(..) {
+                sample(ctx, &mut sequences[sequence])?;
+            }
+        }
+
+        // main loop, decode the next token of every running sequence in one batch
+        loop {
+            batch.clear();
+            for (seq_id, sequence) in (0_i32..).zip(sequences.iter_mut()) {
+                if let Some(token) = sequence.next_token {
+                    batch
+                        .add(token, sequence.n_cur, &[seq_id], true)
+                        .map_err(llama_error)?;
+                    sequence.logits_index = batch.n_tokens() - 1;
+                    sequence.n_cur += 1;
+                }
+            }
+            if batch.n_tokens() == 0 {
+                break;
+            }
+
+            ctx.decode(&mut batch).map_err(llama_error)?;
+
+            for (seq_id, sequence) in (0_u32..).zip(sequences.iter_mut()) {
+                if sequence.next_token.is_some() {
+                    sample(ctx, sequence)?;
+                    if sequence.next_token.is_none() {
+                        // the sequence is done, free its cells for the others
+                        ctx.clear_kv_cache_seq(Some(seq_id), None, None)
+                            .map_err(llama_error)?;
+                    }
+                }
+            }
+        }
+        ctx.clear_kv_cache();
+
+        Ok(sequences
+            .into_iter()
+            .map(|sequence| Completion {
+                text: sequence.completion,
+                finish_reason: sequence.finish_reason.unwrap_or(FinishReason::Length),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,424 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{ParseError, ParsedCompletion};
+
+/// keywords of a line introducing the answer choices, e.g. "Options:", "Choose from:", "Pick your answer from:",
+/// matched as whole words so that "Adopt the following approach:" is not a header
+const OPTIONS_HEADER_KEYWORDS: [&str; 9] = [
+    "option",
+    "options",
+    "choice",
+    "choices",
+    "choose",
+    "pick",
+    "select",
+    "possible answers",
+    "answer from",
+];
+
+/// a multiple-choice question parsed from a "multiple-choice question answering" completion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultipleChoice {
+    /// the question, without the options and `{{context}}`
+    pub question: String,
+    /// the answer choices in the order they appear in the instruction
+    pub options: Vec<String>,
+    /// the labels of the options (e.g. "A", "B"), None for unlabelled options such as "- yes"
+    pub labels: Vec<Option<String>>,
+    /// the index of the option matching the answer after `<|pipe|>`
+    pub answer_index: Option<usize>,
+    /// true if the answer after `<|pipe|>` doesn't match any option
+    pub answer_mismatch: bool,
+    /// the answer after `<|pipe|>` as generated
+    pub answer: String,
+}
+
+/// an answer choice listed in an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChoiceOption {
+    pub label: Option<String>,
+    pub text: String,
+}
+
+/// the answer choices listed in an instruction and the text before them
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChoiceOptions {
+    pub before: String,
+    pub options: Vec<ChoiceOption>,
+}
+
+/// parse a "multiple-choice question answering" completion into question, options and answer
+pub fn parse_mcqa(parsed: &ParsedCompletion) -> Result<MultipleChoice, ParseError> {
+    let ChoiceOptions { before, options } =
+        extract_options(&parsed.instruction).ok_or(ParseError::MissingOptions)?;
+    let question = extract_question(&before).ok_or(ParseError::EmptyQuestion)?;
+    let answer_index = match_option(&options, &parsed.response);
+
+    Ok(MultipleChoice {
+        question,
+        labels: options.iter().map(|option| option.label.clone()).collect(),
+        options: options.into_iter().map(|option| option.text).collect(),
+        answer_index,
+        answer_mismatch: answer_index.is_none(),
+        answer: parsed.response.clone(),
+    })
+}
+
+/// finds the answer choices listed in the instruction, tried in order:
+/// - a header line such as "Options:" followed by one option per line
+/// - a run of at least two bulleted or labelled lines ("- yes", "(A) yes")
+/// - labelled options on a single line ("A) yes B) no")
+pub(crate) fn extract_options(instruction: &str) -> Option<ChoiceOptions> {
+    let lines: Vec<&str> = instruction.lines().collect();
+
+    if let Some(header) = lines.iter().rposition(|line| is_options_header(line)) {
+        let options = options_after_header(&lines[header + 1..]);
+        if !options.is_empty() {
+            return Some(ChoiceOptions {
+                before: lines[..header].join("\n"),
+                options,
+            });
+        }
+    }
+
+    // the last run of list items
+    let mut end = lines.len();
+    while end > 0 {
+        let start = (0..end)
+            .rev()
+            .take_while(|&i| parse_item(lines[i]).is_some())
+            .last();
+        match start {
+            Some(start) if end - start >= 2 => {
+                return Some(ChoiceOptions {
+                    before: lines[..start].join("\n"),
+                    options: lines[start..end]
+                        .iter()
+                        .filter_map(|line| parse_item(line))
+                        .collect(),
+                });
+            }
+            Some(start) => end = start,
+            None => end -= 1,
+        }
+    }
+
+    lines.iter().enumerate().rev().find_map(|(i, line)| {
+        let (before_options, options) = inline_options(line)?;
+        let mut before = lines[..i].join("\n");
+        before.push('\n');
+        before.push_str(before_options);
+        Some(ChoiceOptions { before, options })
+    })
+}
+
+/// true if the line introduces the answer choices
+fn is_options_header(line: &str) -> bool {
+    let line = line.trim().to_lowercase();
+    if !line.ends_with(':') {
+        return false;
+    }
+    let words: Vec<&str> = line
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    OPTIONS_HEADER_KEYWORDS.iter().any(|keyword| {
+        let keyword: Vec<&str> = keyword.split(' ').collect();
+        words
+            .windows(keyword.len())
+            .any(|window| window == keyword.as_slice())
+    })
+}
+
+/// the options listed one per line after a header, with or without bullets/labels
+fn options_after_header(lines: &[&str]) -> Vec<ChoiceOption> {
+    let mut options = vec![];
+    for line in lines.iter().skip_while(|line| line.trim().is_empty()) {
+        if line.trim().is_empty() {
+            break;
+        }
+        match parse_item(line) {
+            Some(option) => options.push(option),
+            // unlabelled options are only accepted when none of the options has a bullet/label
+            None if options
+                .iter()
+                .all(|option: &ChoiceOption| option.label.is_none())
+                && !is_answer_prompt(line) =>
+            {
+                options.push(ChoiceOption {
+                    label: None,
+                    text: line.trim().to_string(),
+                })
+            }
+            None => break,
+        }
+    }
+    options
+}
+
+/// true for lines asking for the answer, e.g. "A:", "Answer:"
+fn is_answer_prompt(line: &str) -> bool {
+    let line = line.trim().to_lowercase();
+    line == "a:" || line.starts_with("answer:") || line.starts_with("the answer")
+}
+
+/// parses a bulleted ("- yes") or labelled ("(A) yes") list item
+fn parse_item(line: &str) -> Option<ChoiceOption> {
+    let line = line.trim();
+    for bullet in ["- ", "* ", "• ", "+ "] {
+        if let Some(text) = line.strip_prefix(bullet) {
+            let text = text.trim();
+            return (!text.is_empty()).then(|| ChoiceOption {
+                label: None,
+                text: text.to_string(),
+            });
+        }
+    }
+    let (label, text) = split_label(line)?;
+    Some(ChoiceOption {
+        label: Some(label),
+        text: text.to_string(),
+    })
+}
+
+/// splits a labelled text such as "(A) yes", "A) yes", "A. yes", "[i] yes" or "1). yes" into label and text
+pub(crate) fn split_label(text: &str) -> Option<(String, &str)> {
+    let (close, rest) = match text.chars().next()? {
+        '(' => (Some(')'), &text[1..]),
+        '[' => (Some(']'), &text[1..]),
+        _ => (None, text),
+    };
+    let label_len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    let label = &rest[..label_len];
+    let is_label = (label.len() == 1 && label.chars().all(|c| c.is_ascii_alphabetic()))
+        || (1..=2).contains(&label.len()) && label.chars().all(|c| c.is_ascii_digit())
+        || (1..=4).contains(&label.len()) && label.chars().all(|c| "ivx".contains(c));
+    if !is_label {
+        return None;
+    }
+
+    let rest = &rest[label_len..];
+    let rest = match close {
+        Some(close) => rest.strip_prefix(close)?,
+        None => rest.strip_prefix(')').or_else(|| rest.strip_prefix('.'))?,
+    };
+    let rest = rest.strip_prefix('.').unwrap_or(rest);
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let text = rest.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some((label.to_uppercase(), text))
+}
+
+/// finds labelled options on a single line, e.g. "Which one? A) yes B) no", returns the text before the options
+fn inline_options(line: &str) -> Option<(&str, Vec<ChoiceOption>)> {
+    // positions of "(A)"/"A)", "(B)"/"B)", ... in order
+    let mut markers: Vec<(usize, usize, char)> = vec![];
+    let mut from = 0;
+    for label in 'A'..='Z' {
+        let found = [format!("({})", label), format!("{})", label)]
+            .iter()
+            .filter_map(|marker| {
+                line[from..]
+                    .match_indices(marker.as_str())
+                    .map(|(i, m)| (from + i, from + i + m.len()))
+                    .find(|&(start, _)| {
+                        line[..start]
+                            .chars()
+                            .last()
+                            .is_none_or(|c| c.is_whitespace())
+                    })
+            })
+            .min();
+        match found {
+            Some((start, end)) => {
+                markers.push((start, end, label));
+                from = end;
+            }
+            None => break,
+        }
+    }
+    if markers.len() < 2 {
+        return None;
+    }
+
+    let options = markers
+        .iter()
+        .enumerate()
+        .map(|(i, &(_, end, label))| {
+            let next = markers
+                .get(i + 1)
+                .map_or(line.len(), |&(start, _, _)| start);
+            ChoiceOption {
+                label: Some(label.to_string()),
+                text: line[end..next].trim().to_string(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if options.iter().any(|option| option.text.is_empty()) {
+        return None;
+    }
+    Some((&line[..markers[0].0], options))
+}
+
+/// the question in the text before the options, after the last "Question:"/"Q:" if any
+fn extract_question(before: &str) -> Option<String> {
+    let before = before.replace("{{context}}", "");
+    let question = ["Question:", "Q:"]
+        .iter()
+        .filter_map(|prefix| before.rfind(prefix).map(|i| &before[i + prefix.len()..]))
+        .min_by_key(|question| question.len())
+        .unwrap_or(&before);
+    let question = question.trim();
+    (!question.is_empty()).then(|| question.to_string())
+}
+
+/// lowercases the text and drops punctuation and repeated whitespace, so "Yes." matches "yes"
+pub(crate) fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the index of the option matching the answer, by text ("yes") or by label ("B", "(B)", "B) yes")
+pub(crate) fn match_option(options: &[ChoiceOption], answer: &str) -> Option<usize> {
+    let answer = answer.trim();
+    let normalized = normalize(answer);
+    if let Some(i) = options
+        .iter()
+        .position(|option| normalize(&option.text) == normalized)
+    {
+        return Some(i);
+    }
+
+    let (label, text) = match split_label(answer) {
+        Some((label, text)) => (label, Some(normalize(text))),
+        None => (normalized.to_uppercase(), None),
+    };
+    options
+        .iter()
+        .position(|option| {
+            option.label.as_deref() == Some(label.as_str())
+                && text
+                    .as_ref()
+                    .is_none_or(|text| *text == normalize(&option.text))
+        })
+        .or_else(|| {
+            let text = text?;
+            options
+                .iter()
+                .position(|option| normalize(&option.text) == text)
+        })
+}
+
+/// the text of the option the answer refers to (e.g. "yes" for "(A)" when the options are "(A) yes (B) no"),
+/// the answer without its label otherwise
+pub(crate) fn resolve_answer(instruction: &str, answer: &str) -> String {
+    if let Some(choices) = extract_options(instruction) {
+        if let Some(i) = match_option(&choices.options, answer) {
+            return choices.options[i].text.clone();
+        }
+    }
+    match split_label(answer.trim()) {
+        Some((_, text)) => text.to_string(),
+        None => answer.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parsed_completion, TaskType};
+
+    const CONTEXT: &str =
+        "The Amazon river flows through Peru, Colombia and Brazil before reaching the Atlantic Ocean.";
+
+    #[test]
+    fn test_is_options_header() {
+        assert!(is_options_header("OPTIONS:"));
+        assert!(is_options_header("Pick your answer from:"));
+        assert!(is_options_header("Possible answers:"));
+        assert!(!is_options_header("Adopt the following approach:"));
+        assert!(!is_options_header("Give an optimistic answer:"));
+        assert!(!is_options_header("Options"));
+    }
+
+    #[test]
+    fn test_parse_mcqa() {
+        // options after a header
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::MultipleChoiceQuestionAnswering,
+            "{{context}}\n\nQ: Which ocean does the Amazon river reach?\n\nChoose your answer from:\n- Pacific Ocean\n- Atlantic Ocean\n- Indian Ocean\n<|pipe|>\nAtlantic Ocean",
+        );
+        let mcqa = parse_mcqa(&parsed).unwrap();
+        assert_eq!(mcqa.question, "Which ocean does the Amazon river reach?");
+        assert_eq!(
+            mcqa.options,
+            vec!["Pacific Ocean", "Atlantic Ocean", "Indian Ocean"]
+        );
+        assert_eq!(mcqa.answer_index, Some(1));
+        assert!(!mcqa.answer_mismatch);
+
+        // labelled options without a header, answered by label
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::MultipleChoiceQuestionAnswering,
+            "Read the text and answer the question.\n{{context}}\nQuestion: Which country is not mentioned?\n(A) Peru\n(B) Chile\n(C) Brazil\n<|pipe|>\n(B)",
+        );
+        let mcqa = parse_mcqa(&parsed).unwrap();
+        assert_eq!(mcqa.question, "Which country is not mentioned?");
+        assert_eq!(mcqa.options, vec!["Peru", "Chile", "Brazil"]);
+        assert_eq!(
+            mcqa.labels,
+            vec![
+                Some("A".to_string()),
+                Some("B".to_string()),
+                Some("C".to_string())
+            ]
+        );
+        assert_eq!(mcqa.answer_index, Some(1));
+
+        // options on the same line as the question
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::MultipleChoiceQuestionAnswering,
+            "{{context}}\nWhere does the Amazon river end? A) in Peru B) in the Atlantic Ocean C) in Colombia\n<|pipe|>\nB) in the Atlantic Ocean.",
+        );
+        let mcqa = parse_mcqa(&parsed).unwrap();
+        assert_eq!(mcqa.question, "Where does the Amazon river end?");
+        assert_eq!(
+            mcqa.options,
+            vec!["in Peru", "in the Atlantic Ocean", "in Colombia"]
+        );
+        assert_eq!(mcqa.answer_index, Some(1));
+
+        // the answer is not one of the options
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::MultipleChoiceQuestionAnswering,
+            "{{context}}\nQ: Which ocean does the Amazon river reach?\nOptions:\n- Pacific Ocean\n- Indian Ocean\nA:\n<|pipe|>\nAtlantic Ocean",
+        );
+        let mcqa = parse_mcqa(&parsed).unwrap();
+        assert_eq!(mcqa.options, vec!["Pacific Ocean", "Indian Ocean"]);
+        assert_eq!(mcqa.answer_index, None);
+        assert!(mcqa.answer_mismatch);
+
+        // no options at all
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::MultipleChoiceQuestionAnswering,
+            "{{context}}\nQ: Which ocean does the Amazon river reach?\n<|pipe|>\nAtlantic Ocean",
+        );
+        assert_eq!(parse_mcqa(&parsed), Err(ParseError::MissingOptions));
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mcqa::{extract_options, normalize, resolve_answer};
+use crate::{ParseError, ParsedCompletion};
+
+/// raw labels meaning the premise entails the hypothesis, compared after `normalize`
+const ENTAILMENT_LABELS: [&str; 9] = [
+    "entailment",
+    "entails",
+    "entailed",
+    "yes",
+    "true",
+    "correct",
+    "definitely correct",
+    "always",
+    "guaranteed true",
+];
+
+/// raw labels meaning the premise neither entails nor contradicts the hypothesis, compared after `normalize`
+const NEUTRAL_LABELS: [&str; 14] = [
+    "neutral",
+    "maybe",
+    "it is not possible to tell",
+    "it s not possible to tell",
+    "not possible to tell",
+    "it is impossible to say",
+    "it s impossible to say",
+    "impossible to tell",
+    "can t tell",
+    "cannot tell",
+    "inconclusive",
+    "sometimes",
+    "possibly",
+    "possibly true",
+];
+
+/// raw labels meaning the premise contradicts the hypothesis, compared after `normalize`
+const CONTRADICTION_LABELS: [&str; 9] = [
+    "contradiction",
+    "contradicts",
+    "contradicted",
+    "no",
+    "false",
+    "incorrect",
+    "definitely incorrect",
+    "never",
+    "impossible",
+];
+
+/// labels of the hypothesis line in an instruction, e.g. "Hypothesis: The sky is blue."
+const HYPOTHESIS_PREFIXES: [&str; 3] = ["Hypothesis:", "Sentence 2:", "Statement:"];
+
+/// normalized label of "natural language inference" and "textual entailment" tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NliLabel {
+    Entailment,
+    Neutral,
+    Contradiction,
+}
+
+/// a premise/hypothesis pair parsed from a "natural language inference" or "textual entailment" completion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Inference {
+    /// the context the task was generated from
+    pub premise: String,
+    /// the hypothesis generated in the instruction
+    pub hypothesis: String,
+    /// the answer after `<|pipe|>` mapped to entailment/neutral/contradiction
+    pub label: NliLabel,
+    /// the answer after `<|pipe|>` as generated
+    pub raw_label: String,
+}
+
+/// parse a "natural language inference"/"textual entailment" completion into premise, hypothesis and label
+pub fn parse_nli(parsed: &ParsedCompletion) -> Result<Inference, ParseError> {
+    let hypothesis =
+        extract_hypothesis(&parsed.instruction).ok_or(ParseError::MissingHypothesis)?;
+    let answer = resolve_answer(&parsed.instruction, &parsed.response);
+    let label = normalize_nli_label(&answer)
+        .ok_or_else(|| ParseError::UnknownLabel(parsed.response.clone()))?;
+
+    Ok(Inference {
+        premise: parsed.context.clone(),
+        hypothesis,
+        label,
+        raw_label: parsed.response.clone(),
+    })
+}
+
+/// maps a raw answer (e.g. "Yes.", "it is not possible to tell", "Contradiction") to a `NliLabel`
+/// None if the answer doesn't look like any of the labels
+pub fn normalize_nli_label(raw_label: &str) -> Option<NliLabel> {
+    let normalized = normalize(raw_label);
+    let labels = [
+        (NliLabel::Neutral, &NEUTRAL_LABELS[..]),
+        (NliLabel::Entailment, &ENTAILMENT_LABELS[..]),
+        (NliLabel::Contradiction, &CONTRADICTION_LABELS[..]),
+    ];
+
+    labels
+        .iter()
+        .find(|(_, raw_labels)| raw_labels.contains(&normalized.as_str()))
+        // an answer starting with a label, e.g. "yes, it is"
+        .or_else(|| {
+            labels.iter().find(|(_, raw_labels)| {
+                raw_labels.iter().any(|raw_label| {
+                    normalized
+                        .strip_prefix(raw_label)
+                        .is_some_and(|rest| rest.starts_with(' '))
+                })
+            })
+        })
+        .map(|(label, _)| *label)
+}
+
+/// finds the hypothesis in the instruction, tried in order:
+/// - a labelled line such as "Hypothesis: ..."
+/// - the last quoted sentence which is not the context
+/// - the last line which is not the context, a question or the options
+fn extract_hypothesis(instruction: &str) -> Option<String> {
+    let non_empty = |text: &str| {
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    };
+
+    for prefix in HYPOTHESIS_PREFIXES {
+        if let Some(i) = instruction.rfind(prefix) {
+            let after_prefix = &instruction[i + prefix.len()..];
+            if let Some(hypothesis) = non_empty(after_prefix.lines().next().unwrap_or_default()) {
+                return Some(hypothesis);
+            }
+        }
+    }
+
+    let quoted = ['"', '“', '”']
+        .iter()
+        .any(|quote| instruction.contains(*quote))
+        .then(|| {
+            instruction
+                .split(['"', '“', '”'])
+                .skip(1)
+                .step_by(2)
+                .filter(|quoted| !quoted.contains("{{context}}"))
+                .filter_map(non_empty)
+                .last()
+        })
+        .flatten();
+    if quoted.is_some() {
+        return quoted;
+    }
+
+    let before_options = match extract_options(instruction) {
+        Some(choices) => choices.before,
+        None => instruction.to_string(),
+    };
+    before_options
+        .lines()
+        .map(|line| line.replace("{{context}}", ""))
+        .filter(|line| {
+            let line = line.trim();
+            !line.ends_with('?') && !line.ends_with(':')
+        })
+        .filter_map(|line| non_empty(&line))
+        .next_back()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parsed_completion, TaskType};
+
+    const CONTEXT: &str = "The Eiffel Tower was completed in 1889 for the World's Fair in Paris.";
+
+    #[test]
+    fn test_parse_nli() {
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::NaturalLanguageInference,
+            "Premise: {{context}}\nHypothesis: The Eiffel Tower is in London.\nDoes the premise entail the hypothesis?\n<|pipe|>\nContradiction",
+        );
+        let inference = parse_nli(&parsed).unwrap();
+        assert_eq!(
+            inference.premise,
+            "The Eiffel Tower was completed in 1889 for the World's Fair in Paris."
+        );
+        assert_eq!(inference.hypothesis, "The Eiffel Tower is in London.");
+        assert_eq!(inference.label, NliLabel::Contradiction);
+        assert_eq!(inference.raw_label, "Contradiction");
+
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::TextualEntailment,
+            "{{context}}\n\nBased on the paragraph above can we conclude that \"The Eiffel Tower was built in the 19th century.\"?\n\nOPTIONS:\n- Yes\n- It's impossible to say\n- No\n<|pipe|>\nYes.",
+        );
+        let inference = parse_nli(&parsed).unwrap();
+        assert_eq!(
+            inference.hypothesis,
+            "The Eiffel Tower was built in the 19th century."
+        );
+        assert_eq!(inference.label, NliLabel::Entailment);
+        assert_eq!(inference.raw_label, "Yes.");
+
+        // the hypothesis on its own line and the answer given by option label
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::TextualEntailment,
+            "{{context}}\nCan we draw the following hypothesis from the context?\nThe Eiffel Tower was built for a World's Fair.\n(A) yes (B) it is not possible to tell (C) no\n<|pipe|>\n(B)",
+        );
+        let inference = parse_nli(&parsed).unwrap();
+        assert_eq!(
+            inference.hypothesis,
+            "The Eiffel Tower was built for a World's Fair."
+        );
+        assert_eq!(inference.label, NliLabel::Neutral);
+
+        let parsed = parsed_completion(
+            CONTEXT,
+            &TaskType::NaturalLanguageInference,
+            "Premise: {{context}}\nHypothesis: The Eiffel Tower is in Paris.\n<|pipe|>\nParis",
+        );
+        assert_eq!(
+            parse_nli(&parsed),
+            Err(ParseError::UnknownLabel("Paris".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_normalize_nli_label() {
+        assert_eq!(
+            normalize_nli_label("entailment"),
+            Some(NliLabel::Entailment)
+        );
+        assert_eq!(
+            normalize_nli_label("Yes, it is."),
+            Some(NliLabel::Entailment)
+        );
+        assert_eq!(normalize_nli_label("Neutral"), Some(NliLabel::Neutral));
+        assert_eq!(
+            normalize_nli_label("It is not possible to tell"),
+            Some(NliLabel::Neutral)
+        );
+        assert_eq!(
+            normalize_nli_label("it's impossible to say"),
+            Some(NliLabel::Neutral)
+        );
+        assert_eq!(normalize_nli_label("No"), Some(NliLabel::Contradiction));
+        assert_eq!(normalize_nli_label("FALSE"), Some(NliLabel::Contradiction));
+        assert_eq!(normalize_nli_label("the tower"), None);
+    }
+}
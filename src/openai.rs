@@ -0,0 +1,396 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::generator::{Completion, FinishReason, GenerateError, Generator};
+use crate::grammar::prompt_grammar;
+use crate::sampling::SamplingParams;
+use crate::stop::StopCriteria;
+
+/// an OpenAI-compatible completions endpoint serving bonito, e.g. llama-server or vLLM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenAiConfig {
+    /// the base URL of the API, `/completions` is appended, e.g. "http://localhost:8080/v1"
+    pub base_url: String,
+    /// the model name sent in the requests
+    pub model: String,
+    /// sent as a bearer token if set
+    pub api_key: Option<String>,
+    /// timeout of each request
+    pub timeout: Duration,
+    /// how many times a request is retried on connection errors, 429 and 5xx
+    pub retries: u32,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:8080/v1".to_string(),
+            model: "bonito".to_string(),
+            api_key: None,
+            timeout: Duration::from_secs(120),
+            retries: 2,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    max_tokens: usize,
+    temperature: f32,
+    top_p: f32,
+    // the sampling params of the llama.cpp server beyond the OpenAI API, vLLM takes top_k and min_p too
+    top_k: i32,
+    min_p: f32,
+    typical_p: f32,
+    repeat_penalty: f32,
+    repeat_last_n: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    /// the GBNF grammar of `SamplingParams::constrained`, only the llama.cpp server supports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grammar: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    text: String,
+    /// "stop" or "length", "stop" is also the finish reason of EOS
+    finish_reason: Option<String>,
+    /// the stop string or token which stopped the generation, null at EOS, sent by vLLM
+    #[serde(default)]
+    stop_reason: Option<serde::de::IgnoredAny>,
+    /// the stop string which stopped the generation, empty at EOS, sent by the llama.cpp server
+    #[serde(default)]
+    stopping_word: Option<String>,
+}
+
+impl CompletionChoice {
+    /// a "stop" is a stop string if the server says which one, EOS otherwise
+    fn finish_reason(&self) -> FinishReason {
+        match self.finish_reason.as_deref() {
+            Some("length") => FinishReason::Length,
+            Some("stop")
+                if self.stop_reason.is_some()
+                    || self
+                        .stopping_word
+                        .as_ref()
+                        .is_some_and(|word| !word.is_empty()) =>
+            {
+                FinishReason::Stop
+            }
+            _ => FinishReason::Eos,
+        }
+    }
+}
+
+/// generates the completions with an OpenAI-compatible `/completions` endpoint, one request per prompt
+pub struct OpenAiGenerator {
+    config: OpenAiConfig,
+    agent: ureq::Agent,
+}
+
+impl OpenAiGenerator {
+    pub fn new(config: OpenAiConfig) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(config.timeout).build();
+        Self { config, agent }
+    }
+
+    /// returns the text generated after the prompt and why it stopped
+    fn complete(
+        &self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<(String, FinishReason), GenerateError> {
+        let url = format!("{}/completions", self.config.base_url.trim_end_matches('/'));
+        let request = CompletionRequest {
+            model: &self.config.model,
+            prompt,
+            max_tokens: params.max_tokens,
+            temperature: if params.is_greedy() {
+                0.0
+            } else {
+                params.temperature
+            },
+            top_p: params.top_p,
+            top_k: params.top_k,
+            min_p: params.min_p,
+            typical_p: params.typical_p,
+            repeat_penalty: params.repeat_penalty,
+            repeat_last_n: params.repeat_last_n,
+            seed: params.seed,
+            stop: &params.stop,
+            grammar: params.constrained.then(|| prompt_grammar(prompt)),
+        };
+
+        let mut attempt = 0;
+        loop {
+            let mut call = self.agent.post(&url);
+            if let Some(api_key) = &self.config.api_key {
+                call = call.set("Authorization", &format!("Bearer {}", api_key));
+            }
+            let error = match call.send_json(&request) {
+                Ok(response) => {
+                    let response: CompletionResponse = response.into_json().map_err(|err| {
+                        GenerateError::Backend(format!("invalid response from {}: {}", url, err))
+                    })?;
+                    return response
+                        .choices
+                        .into_iter()
+                        .next()
+                        .map(|choice| {
+                            let finish_reason = choice.finish_reason();
+                            (choice.text, finish_reason)
+                        })
+                        .ok_or_else(|| {
+                            GenerateError::Backend(format!("no choices in the response of {}", url))
+                        });
+                }
+                Err(ureq::Error::Status(status, response)) => {
+                    let body = response.into_string().unwrap_or_default();
+                    let error = format!("{} returned {}: {}", url, status, body);
+                    if status != 429 && status < 500 {
+                        return Err(GenerateError::Backend(error));
+                    }
+                    error
+                }
+                Err(err) => err.to_string(),
+            };
+
+            if attempt >= self.config.retries {
+                return Err(GenerateError::Backend(error));
+            }
+            // 250ms, 500ms, 1s...
+            std::thread::sleep(Duration::from_millis(250 << attempt.min(6)));
+            attempt += 1;
+        }
+    }
+}
+
+impl Generator for OpenAiGenerator {
+    /// sends the prompts concurrently, one request each, the prompt `i` with the seed `seed + i`,
+    /// the server stops at the stop strings and the answer line is cut from the returned text
+    fn generate_batch(
+        &mut self,
+        prompts: &[&str],
+        params: &SamplingParams,
+    ) -> Result<Vec<Completion>, GenerateError> {
+        let generator = &*self;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = prompts
+                .iter()
+                .enumerate()
+                .map(|(i, prompt)| {
+                    let params = params.offset_seed(i);
+                    scope.spawn(move || generator.complete(prompt, &params))
+                })
+                .collect();
+            prompts
+                .iter()
+                .zip(handles)
+                .map(|(prompt, handle)| {
+                    let (mut text, mut finish_reason) = handle.join().map_err(|_| {
+                        GenerateError::Backend("request thread panicked".to_string())
+                    })??;
+                    if let Some(end) =
+                        StopCriteria::new(prompt, params).find_stop(&text, text.len())
+                    {
+                        text.truncate(end);
+                        finish_reason = FinishReason::Stop;
+                    }
+                    Ok(Completion {
+                        text: format!("{}{}", prompt, text),
+                        finish_reason,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// serves the responses (status, body) in turn, returns the base URL and the received requests
+    fn mock_server(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut headers = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                    headers.push_str(&line);
+                }
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+                requests.push((headers, String::from_utf8(request_body).unwrap()));
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (base_url, handle)
+    }
+
+    #[test]
+    fn test_openai_generator() {
+        let (base_url, server) = mock_server(vec![
+            (503, r#"{"error": "loading model"}"#),
+            (
+                200,
+                r#"{"choices": [{"index": 0, "text": "{{context}}\nWhat is the capital of France?\n<|pipe|>\nParis", "finish_reason": "stop"}]}"#,
+            ),
+        ]);
+        let mut generator = OpenAiGenerator::new(OpenAiConfig {
+            base_url,
+            api_key: Some("secret".to_string()),
+            retries: 1,
+            ..Default::default()
+        });
+        let params = SamplingParams {
+            seed: Some(7),
+            stop: vec!["###".to_string()],
+            ..Default::default()
+        };
+
+        let task = generator
+            .generate_task(
+                "Paris is the capital of France.",
+                &crate::TaskType::ExtractiveQuestionAnswering,
+                &params,
+            )
+            .unwrap();
+        assert_eq!(task.completion.response, "Paris");
+        // a "stop" without the stop string is EOS
+        assert_eq!(task.finish_reason, Some(FinishReason::Eos));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (headers, body) = &requests[1];
+        assert!(headers.starts_with("POST /v1/completions "));
+        assert!(headers.contains("Bearer secret"));
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["model"], "bonito");
+        assert_eq!(body["seed"], 7);
+        assert_eq!(body["stop"], serde_json::json!(["###"]));
+        assert_eq!(body["top_k"], 40);
+        assert_eq!(body["repeat_penalty"], 1.1f32);
+        assert!(body.get("grammar").is_none());
+        assert!(body["prompt"]
+            .as_str()
+            .unwrap()
+            .ends_with("Paris is the capital of France.\n<|task|>\n "));
+    }
+
+    #[test]
+    fn test_openai_generator_seeds() {
+        let response = r#"{"choices": [{"index": 0, "text": "{{context}}\nWhere?\n<|pipe|>\nParis", "finish_reason": "stop"}]}"#;
+        let (base_url, server) = mock_server(vec![(200, response); 3]);
+        let mut generator = OpenAiGenerator::new(OpenAiConfig {
+            base_url,
+            ..Default::default()
+        });
+        let params = SamplingParams {
+            seed: Some(7),
+            ..Default::default()
+        };
+
+        // the samples of a prompt
+        let completions = generator.generate_batch(&["prompt"; 3], &params).unwrap();
+        assert_eq!(completions.len(), 3);
+
+        let mut seeds: Vec<u64> = server
+            .join()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| {
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                body["seed"].as_u64().unwrap()
+            })
+            .collect();
+        // the requests are sent concurrently
+        seeds.sort();
+        assert_eq!(seeds, [7, 8, 9]);
+    }
+
+    #[test]
+    fn test_finish_reason() {
+        let finish_reason = |choice: &str| {
+            serde_json::from_str::<CompletionChoice>(choice)
+                .unwrap()
+                .finish_reason()
+        };
+        assert_eq!(
+            finish_reason(r#"{"text": "", "finish_reason": "length"}"#),
+            FinishReason::Length
+        );
+        assert_eq!(
+            finish_reason(r#"{"text": "", "finish_reason": "stop", "stop_reason": null}"#),
+            FinishReason::Eos
+        );
+        assert_eq!(
+            finish_reason(r#"{"text": "", "finish_reason": "stop", "stop_reason": "Q:"}"#),
+            FinishReason::Stop
+        );
+        assert_eq!(
+            finish_reason(r#"{"text": "", "finish_reason": "stop", "stopping_word": "Q:"}"#),
+            FinishReason::Stop
+        );
+        assert_eq!(
+            finish_reason(r#"{"text": "", "finish_reason": "stop", "stopping_word": ""}"#),
+            FinishReason::Eos
+        );
+    }
+
+    #[test]
+    fn test_openai_generator_errors() {
+        let (base_url, server) = mock_server(vec![(401, r#"{"error": "invalid api key"}"#)]);
+        let mut generator = OpenAiGenerator::new(OpenAiConfig {
+            base_url,
+            ..Default::default()
+        });
+        // 4xx errors are not retried
+        let params = SamplingParams {
+            constrained: true,
+            ..Default::default()
+        };
+        let err = generator.generate("prompt", &params).unwrap_err();
+        assert!(err.to_string().contains("401"));
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(body["grammar"], prompt_grammar("prompt"));
+    }
+}
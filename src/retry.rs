@@ -0,0 +1,295 @@
+use serde::Serialize;
+
+use crate::generator::{Completion, Delta, FinishReason, GenerateError, Generator};
+use crate::sampling::SamplingParams;
+use crate::sanitize::ContextPolicy;
+use crate::{parse_task_with, ParseError, ParsedTask};
+
+/// how many times the completion of a prompt is generated again when it fails to parse, and with which params
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// the attempts per prompt including the first one, 1 disables the retries
+    pub max_attempts: usize,
+    /// added to the temperature at every retry, a greedy generation samples its retries if it's > 0
+    pub temperature_step: f32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            temperature_step: 0.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// the params of the attempt `attempt`, 0 is the first attempt with the params unchanged
+    /// the sequences of a batch add their index to the seed, the retries add `attempt << 32` so the seeds never collide,
+    /// without a seed every attempt samples with a random one anyway
+    pub fn attempt_params(&self, params: &SamplingParams, attempt: usize) -> SamplingParams {
+        let mut attempt_params = params.clone();
+        if attempt == 0 {
+            return attempt_params;
+        }
+        attempt_params.seed = params
+            .seed
+            .map(|seed| seed.wrapping_add((attempt as u64) << 32));
+        if self.temperature_step > 0.0 {
+            let temperature = if params.is_greedy() {
+                0.0
+            } else {
+                params.temperature
+            };
+            attempt_params.temperature = temperature + self.temperature_step * attempt as f32;
+            attempt_params.greedy = false;
+        }
+        attempt_params
+    }
+}
+
+/// an attempt of `generate_parsed`, recorded in the task or in the failure when retrying
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attempt {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// 0 if the attempt was greedy
+    pub temperature: f32,
+    /// None if the generation failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+    /// why the attempt was rejected, the kind of the parse error (e.g. "ungrounded_answer") or "generation_failed",
+    /// None for the accepted attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Attempt {
+    fn new(params: &SamplingParams, finish_reason: Option<FinishReason>) -> Self {
+        Self {
+            seed: params.seed,
+            temperature: if params.is_greedy() {
+                0.0
+            } else {
+                params.temperature
+            },
+            finish_reason,
+            reason: None,
+            error: None,
+        }
+    }
+}
+
+/// a prompt whose every attempt failed to parse
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedTask {
+    /// the completion of the last generated attempt
+    pub completion: Completion,
+    pub error: ParseError,
+    /// every attempt if the policy retries, empty otherwise
+    pub attempts: Vec<Attempt>,
+}
+
+/// generates and parses the completions of the prompts, the prompts whose completion fails to parse (an empty answer,
+/// an answer not found in the context...) are generated again together with the params of the next attempt,
+/// until they parse or `max_attempts`
+/// fails only if the first generation fails, a failed retry ends the retries and is recorded as an attempt
+pub fn generate_parsed<G: Generator + ?Sized>(
+    generator: &mut G,
+    prompts: &[&str],
+    params: &SamplingParams,
+    retry: &RetryPolicy,
+    policy: ContextPolicy,
+) -> Result<Vec<Result<ParsedTask, FailedTask>>, GenerateError> {
+    generate_parsed_streaming(generator, prompts, params, retry, policy, &mut |_| {})
+}
+
+/// `generate_parsed` calling `on_delta` with the pieces of the generated texts of every attempt,
+/// with the index of the prompt in `prompts` and the attempt
+pub fn generate_parsed_streaming<G: Generator + ?Sized>(
+    generator: &mut G,
+    prompts: &[&str],
+    params: &SamplingParams,
+    retry: &RetryPolicy,
+    policy: ContextPolicy,
+    on_delta: &mut dyn FnMut(Delta),
+) -> Result<Vec<Result<ParsedTask, FailedTask>>, GenerateError> {
+    let mut results: Vec<Option<Result<ParsedTask, FailedTask>>> = vec![None; prompts.len()];
+    let mut attempts: Vec<Vec<Attempt>> = vec![vec![]; prompts.len()];
+    let mut pending: Vec<usize> = (0..prompts.len()).collect();
+
+    for attempt in 0..retry.max_attempts.max(1) {
+        if pending.is_empty() {
+            break;
+        }
+        let attempt_params = retry.attempt_params(params, attempt);
+        let batch: Vec<&str> = pending.iter().map(|&i| prompts[i]).collect();
+        let mut on_batch_delta = |delta: Delta| {
+            on_delta(Delta {
+                index: pending[delta.index],
+                attempt,
+                ..delta
+            })
+        };
+        let completions = match generator.generate_batch_streaming(
+            &batch,
+            &attempt_params,
+            &mut on_batch_delta,
+        ) {
+            Ok(completions) => completions,
+            Err(err) if attempt == 0 => return Err(err),
+            Err(err) => {
+                // the failures of the previous attempt are kept
+                for &i in &pending {
+                    attempts[i].push(Attempt {
+                        reason: Some("generation_failed"),
+                        error: Some(err.to_string()),
+                        ..Attempt::new(&attempt_params, None)
+                    });
+                }
+                break;
+            }
+        };
+
+        let mut failed = vec![];
+        for (i, completion) in pending.into_iter().zip(completions) {
+            let mut record = Attempt::new(&attempt_params, Some(completion.finish_reason));
+            match parse_task_with(&completion.text, policy) {
+                Ok(mut task) => {
+                    task.finish_reason = Some(completion.finish_reason);
+                    task.completion_text = Some(completion.text);
+                    results[i] = Some(Ok(task));
+                }
+                Err(error) => {
+                    record.reason = Some(error.kind());
+                    record.error = Some(error.to_string());
+                    results[i] = Some(Err(FailedTask {
+                        completion,
+                        error,
+                        attempts: vec![],
+                    }));
+                    failed.push(i);
+                }
+            }
+            attempts[i].push(record);
+        }
+        pending = failed;
+    }
+
+    Ok(results
+        .into_iter()
+        .zip(attempts)
+        .map(|(result, attempts)| {
+            let attempts = if retry.max_attempts > 1 {
+                attempts
+            } else {
+                vec![]
+            };
+            match result.expect("every prompt is generated at least once") {
+                Ok(task) => Ok(ParsedTask { attempts, ..task }),
+                Err(failed) => Err(FailedTask { attempts, ..failed }),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::MockGenerator;
+    use crate::{prepare_prompt, TaskType};
+
+    #[test]
+    fn test_generate_parsed() {
+        // the mock answers in turn, the second prompt gets "Berlin", an empty answer then "Bern"
+        let mut generator = MockGenerator::new([
+            "{{context}}\nWhere?\n<|pipe|>\nParis",
+            "{{context}}\nWhere?\n<|pipe|>\nBerlin",
+            "{{context}}\nWhere?\n<|pipe|>\n",
+            "{{context}}\nWhere?\n<|pipe|>\nBern",
+        ]);
+        let prompts = [
+            prepare_prompt(
+                "Paris is in France.",
+                &TaskType::ExtractiveQuestionAnswering,
+            ),
+            prepare_prompt(
+                "Bern is in Switzerland.",
+                &TaskType::ExtractiveQuestionAnswering,
+            ),
+        ];
+        let prompts: Vec<&str> = prompts.iter().map(String::as_str).collect();
+        let params = SamplingParams {
+            seed: Some(1),
+            ..Default::default()
+        };
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            temperature_step: 0.25,
+        };
+
+        let mut streamed = String::new();
+        let results = generate_parsed_streaming(
+            &mut generator,
+            &prompts,
+            &params,
+            &retry,
+            ContextPolicy::default(),
+            &mut |delta| {
+                if delta.index == 1 && delta.attempt == 2 {
+                    streamed.push_str(delta.text);
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(generator.prompts.len(), 4);
+        assert_eq!(streamed, "{{context}}\nWhere?\n<|pipe|>\nBern");
+
+        let paris = results[0].as_ref().unwrap();
+        assert_eq!(paris.completion.response, "Paris");
+        assert_eq!(paris.attempts.len(), 1);
+
+        let bern = results[1].as_ref().unwrap();
+        assert_eq!(bern.completion.response, "Bern");
+        let reasons: Vec<_> = bern.attempts.iter().map(|attempt| attempt.reason).collect();
+        assert_eq!(
+            reasons,
+            [Some("ungrounded_answer"), Some("empty_answer"), None]
+        );
+        assert_eq!(bern.attempts[2].seed, Some(1 + (2 << 32)));
+        assert_eq!(bern.attempts[2].temperature, 1.5);
+
+        // without retries the failures are returned as they are
+        let mut generator = MockGenerator::new(["{{context}}\nWhere?\n<|pipe|>\nParis"]);
+        let results = generate_parsed(
+            &mut generator,
+            &prompts[1..],
+            &params,
+            &RetryPolicy::default(),
+            ContextPolicy::default(),
+        )
+        .unwrap();
+        let failed = results[0].as_ref().unwrap_err();
+        assert_eq!(failed.error, ParseError::UngroundedAnswer);
+        assert!(failed.attempts.is_empty());
+    }
+
+    #[test]
+    fn test_attempt_params() {
+        let params = SamplingParams {
+            greedy: true,
+            ..Default::default()
+        };
+        let retry = RetryPolicy {
+            max_attempts: 2,
+            temperature_step: 0.5,
+        };
+        assert_eq!(retry.attempt_params(&params, 0), params);
+        let retry_params = retry.attempt_params(&params, 1);
+        assert!(!retry_params.is_greedy());
+        assert_eq!(retry_params.temperature, 0.5);
+        assert_eq!(retry_params.seed, None);
+    }
+}
@@ -0,0 +1,163 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+/// sampling parameters for generating completions, mirrors the `SamplingParams` used by python bonito
+/// ref https://github.com/BatsResearch/bonito?tab=readme-ov-file#basic-usage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingParams {
+    /// max number of tokens to generate after the prompt
+    pub max_tokens: usize,
+    /// randomness of the sampling, 0 is the same as `greedy`
+    pub temperature: f32,
+    /// keep only the `top_k` most likely tokens, 0 keeps all
+    pub top_k: i32,
+    /// keep the most likely tokens whose cumulative probability is at most `top_p`
+    pub top_p: f32,
+    /// drop tokens less likely than `min_p` times the most likely token
+    pub min_p: f32,
+    /// tail free sampling, 1.0 is disabled, ignored by the llama backend since llama.cpp dropped it
+    pub tfs_z: f32,
+    /// locally typical sampling, 1.0 is disabled
+    pub typical_p: f32,
+    /// penalty for repeating one of the last `repeat_last_n` tokens, 1.0 is disabled
+    pub repeat_penalty: f32,
+    /// how many of the last tokens `repeat_penalty` looks at
+    pub repeat_last_n: usize,
+    /// seed for reproducible sampling, random if None
+    pub seed: Option<u64>,
+    /// always pick the most likely token instead of sampling from the distribution
+    pub greedy: bool,
+    /// stop once the generated text contains one of these strings, the stop string is not kept
+    pub stop: Vec<String>,
+    /// stop after the line of the answer following `<|pipe|>`, by default only for the short answer
+    /// task types of `stop::is_short_answer`
+    pub stop_after_answer: Option<bool>,
+    /// constrain the generated text to the shape of a bonito completion with the grammar of
+    /// `grammar::prompt_grammar`, an instruction, one `<|pipe|>` and a non-blank response
+    pub constrained: bool,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            max_tokens: 512,
+            temperature: 1.0,
+            top_k: 40,
+            top_p: 0.95,
+            min_p: 0.05,
+            tfs_z: 1.0,
+            typical_p: 1.0,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            seed: None,
+            greedy: false,
+            stop: vec![],
+            stop_after_answer: None,
+            constrained: false,
+        }
+    }
+}
+
+impl SamplingParams {
+    /// true if the most likely token is always picked
+    pub fn is_greedy(&self) -> bool {
+        self.greedy || self.temperature <= 0.0
+    }
+
+    /// the params of a batch following `offset` generated prompts, the prompt `i` of a batch samples with
+    /// `seed + i` so the seed is offset to give every prompt of the run its own seed, e.g. the samples of a context
+    pub fn offset_seed(&self, offset: usize) -> Self {
+        Self {
+            seed: self.seed.map(|seed| seed.wrapping_add(offset as u64)),
+            ..self.clone()
+        }
+    }
+}
+
+/// picks the next token from the probabilities of the candidates, greedily or at random
+pub struct TokenPicker {
+    greedy: bool,
+    rng: StdRng,
+}
+
+impl TokenPicker {
+    pub fn new(params: &SamplingParams) -> Self {
+        let rng = match params.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            greedy: params.is_greedy(),
+            rng,
+        }
+    }
+
+    /// returns the index of the picked candidate
+    /// the most likely candidate if greedy or if the probabilities can't be sampled from (e.g. all zeros)
+    pub fn pick(&mut self, probabilities: &[f32]) -> usize {
+        let most_likely = || {
+            probabilities
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| b.total_cmp(a))
+                .map_or(0, |(i, _)| i)
+        };
+        if self.greedy {
+            return most_likely();
+        }
+        match WeightedIndex::new(probabilities.iter().map(|p| p.max(0.0))) {
+            Ok(distribution) => distribution.sample(&mut self.rng),
+            Err(_) => most_likely(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_picker() {
+        let probabilities = [0.1, 0.6, 0.3];
+
+        let params = SamplingParams {
+            greedy: true,
+            ..Default::default()
+        };
+        let mut picker = TokenPicker::new(&params);
+        assert!((0..10).all(|_| picker.pick(&probabilities) == 1));
+
+        // the same seed picks the same candidates
+        let params = SamplingParams {
+            seed: Some(42),
+            ..Default::default()
+        };
+        let picks: Vec<usize> = {
+            let mut picker = TokenPicker::new(&params);
+            (0..100).map(|_| picker.pick(&probabilities)).collect()
+        };
+        let mut picker = TokenPicker::new(&params);
+        assert_eq!(
+            picks,
+            (0..100)
+                .map(|_| picker.pick(&probabilities))
+                .collect::<Vec<_>>()
+        );
+        // and samples from the distribution instead of taking the top candidate
+        assert!(picks.contains(&0) && picks.contains(&1) && picks.contains(&2));
+
+        assert_eq!(picker.pick(&[0.0, 0.0]), 0);
+    }
+
+    #[test]
+    fn test_sampling_params_from_json() {
+        let params: SamplingParams =
+            serde_json::from_str(r#"{"temperature": 0.5, "seed": 7}"#).unwrap();
+        assert_eq!(params.temperature, 0.5);
+        assert_eq!(params.seed, Some(7));
+        assert_eq!(params.top_p, SamplingParams::default().top_p);
+    }
+}
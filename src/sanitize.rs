@@ -0,0 +1,205 @@
+use std::borrow::Cow;
+
+/// the strings of a context which would be taken for the delimiters of the prompt or of the completion
+pub const RESERVED: [&str; 5] = [
+    "<|tasktype|>",
+    "<|context|>",
+    "<|task|>",
+    "<|pipe|>",
+    "{{context}}",
+];
+
+/// what `prepare_prompt_with` does with the special tokens and `{{context}}` found in a context,
+/// parse the completions with the same policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextPolicy {
+    /// escapes the reserved strings, e.g. `<|pipe|>` as `<\|pipe|>` and `{{context}}` as `{\{context}}`,
+    /// the parsers unescape the context and the generated text
+    #[default]
+    Escape,
+    /// removes the reserved strings from the context
+    Strip,
+    /// fails with `ContextError` if the context contains a reserved string
+    Reject,
+}
+
+impl std::str::FromStr for ContextPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "escape" => Ok(ContextPolicy::Escape),
+            "strip" => Ok(ContextPolicy::Strip),
+            "reject" => Ok(ContextPolicy::Reject),
+            _ => Err(format!(
+                "unknown context policy \"{}\", expected escape, strip or reject",
+                policy
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ContextPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContextPolicy::Escape => write!(f, "escape"),
+            ContextPolicy::Strip => write!(f, "strip"),
+            ContextPolicy::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// a context rejected by `ContextPolicy::Reject`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextError {
+    /// the first reserved string found in the context
+    pub reserved: &'static str,
+}
+
+impl std::fmt::Display for ContextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the context contains the reserved string {}",
+            self.reserved
+        )
+    }
+}
+
+impl std::error::Error for ContextError {}
+
+/// applies the policy to a context before it is put in a prompt
+pub fn sanitize_context(
+    context: &str,
+    policy: ContextPolicy,
+) -> Result<Cow<'_, str>, ContextError> {
+    match policy {
+        ContextPolicy::Escape => Ok(escape(context)),
+        ContextPolicy::Strip => Ok(strip(context)),
+        ContextPolicy::Reject => match RESERVED.iter().find(|reserved| context.contains(*reserved))
+        {
+            Some(reserved) => Err(ContextError { reserved }),
+            None => Ok(Cow::Borrowed(context)),
+        },
+    }
+}
+
+/// reverts `sanitize_context` on a parsed text, only `ContextPolicy::Escape` can be reverted
+pub fn desanitize(text: &str, policy: ContextPolicy) -> Cow<'_, str> {
+    match policy {
+        ContextPolicy::Escape => unescape(text),
+        ContextPolicy::Strip | ContextPolicy::Reject => Cow::Borrowed(text),
+    }
+}
+
+/// removes the reserved strings until there are none, e.g. "<|pi<|task|>pe|>" is removed entirely
+fn strip(context: &str) -> Cow<'_, str> {
+    let mut context = Cow::Borrowed(context);
+    while let Some(reserved) = RESERVED.iter().find(|reserved| context.contains(*reserved)) {
+        context = Cow::Owned(context.replace(reserved, ""));
+    }
+    context
+}
+
+/// adds (`escape`) or removes (`!escape`) a backslash after the first character of the reserved strings,
+/// already escaped reserved strings get one more backslash so that escaping is reversible, the other
+/// special tokens and placeholders like `<|endoftext|>` or `{{name}}` are kept
+fn toggle_escapes(text: &str, escape: bool) -> Cow<'_, str> {
+    if !RESERVED
+        .iter()
+        .any(|reserved| text.contains(&reserved[1..]))
+    {
+        return Cow::Borrowed(text);
+    }
+
+    let mut toggled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(|c| RESERVED.iter().any(|reserved| reserved.starts_with(c))) {
+        let first = &rest[i..=i];
+        toggled.push_str(&rest[..=i]);
+        rest = &rest[i + 1..];
+
+        let after_backslashes = rest.trim_start_matches('\\');
+        let n_backslashes = rest.len() - after_backslashes.len();
+        let is_reserved = RESERVED.iter().any(|reserved| {
+            reserved.starts_with(first) && after_backslashes.starts_with(&reserved[1..])
+        });
+        if is_reserved {
+            if escape {
+                toggled.push('\\');
+                toggled.push_str(&rest[..n_backslashes]);
+            } else {
+                toggled.push_str(&rest[..n_backslashes.saturating_sub(1)]);
+            }
+        } else {
+            toggled.push_str(&rest[..n_backslashes]);
+        }
+        rest = after_backslashes;
+    }
+    toggled.push_str(rest);
+    Cow::Owned(toggled)
+}
+
+pub(crate) fn escape(text: &str) -> Cow<'_, str> {
+    toggle_escapes(text, true)
+}
+
+fn unescape(text: &str) -> Cow<'_, str> {
+    toggle_escapes(text, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_sanitize_context() {
+        let context = "a {{context}} template <|pipe|> and <\\|task|>";
+        assert_eq!(
+            sanitize_context(context, ContextPolicy::Escape).unwrap(),
+            "a {\\{context}} template <\\|pipe|> and <\\\\|task|>"
+        );
+        assert_eq!(
+            sanitize_context(context, ContextPolicy::Strip).unwrap(),
+            "a  template  and <\\|task|>"
+        );
+        assert_eq!(
+            sanitize_context(context, ContextPolicy::Reject),
+            Err(ContextError {
+                reserved: "<|pipe|>"
+            })
+        );
+        assert_eq!(
+            sanitize_context("<|pi<|task|>pe|>", ContextPolicy::Strip).unwrap(),
+            ""
+        );
+        assert!(matches!(
+            sanitize_context("a plain context", ContextPolicy::Escape).unwrap(),
+            Cow::Borrowed(_)
+        ));
+        // only the reserved strings are escaped
+        let context = "Hello {{name}} <|endoftext|> {{{context}}} <|<|task|>";
+        assert_eq!(
+            sanitize_context(context, ContextPolicy::Escape).unwrap(),
+            "Hello {{name}} <|endoftext|> {{\\{context}}} <|<\\|task|>"
+        );
+        assert_eq!(
+            desanitize("Hello {{name}} <\\|endoftext|>", ContextPolicy::Escape),
+            "Hello {{name}} <\\|endoftext|>"
+        );
+        assert_eq!("strip".parse(), Ok(ContextPolicy::Strip));
+        assert!("drop".parse::<ContextPolicy>().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn escape_is_reversible(text in "([<{|}>\\\\a]|<\\|pipe\\|>|<\\|task\\|>|\\{\\{context\\}\\}){0,12}") {
+            let escaped = escape(&text);
+            prop_assert_eq!(unescape(&escaped), text.as_str());
+            for reserved in RESERVED {
+                prop_assert!(!escaped.contains(reserved));
+            }
+        }
+    }
+}
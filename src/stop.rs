@@ -0,0 +1,159 @@
+use crate::sampling::SamplingParams;
+use crate::{prompt_task_type, TaskType};
+
+/// true for the task types answered with a label or a short span on the line after `<|pipe|>`,
+/// their generation stops after that line unless `SamplingParams::stop_after_answer` is false
+pub fn is_short_answer(task_type: &TaskType) -> bool {
+    matches!(
+        task_type,
+        TaskType::ExtractiveQuestionAnswering
+            | TaskType::MultipleChoiceQuestionAnswering
+            | TaskType::QuestionAnsweringWithoutChoices
+            | TaskType::YesNoQuestionAnswering
+            | TaskType::CoreferenceResolution
+            | TaskType::ParaphraseIdentification
+            | TaskType::Sentiment
+            | TaskType::TopicClassification
+            | TaskType::WordSenseDisambiguation
+            | TaskType::TextualEntailment
+            | TaskType::NaturalLanguageInference
+    )
+}
+
+/// decides when the generated text of a prompt is complete, checked after every new token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopCriteria {
+    stop: Vec<String>,
+    after_answer: bool,
+}
+
+impl StopCriteria {
+    /// the stop strings of the params, and the answer line if `stop_after_answer` or by default if the
+    /// task type of the prompt is a short answer one
+    pub fn new(prompt: &str, params: &SamplingParams) -> Self {
+        let after_answer = params.stop_after_answer.unwrap_or_else(|| {
+            prompt_task_type(prompt).is_some_and(|task_type| is_short_answer(&task_type))
+        });
+        Self {
+            stop: params
+                .stop
+                .iter()
+                .filter(|stop| !stop.is_empty())
+                .cloned()
+                .collect(),
+            after_answer,
+        }
+    }
+
+    /// true if nothing would ever stop the generation before EOS or `max_tokens`
+    pub fn is_empty(&self) -> bool {
+        self.stop.is_empty() && !self.after_answer
+    }
+
+    /// the length of the generated text which no stop string can cut anymore, the text after it may be
+    /// the beginning of a stop string, e.g. to stream only the text which is kept
+    pub fn settled_len(&self, generated: &str) -> usize {
+        let max_stop_len = self.stop.iter().map(String::len).max().unwrap_or_default();
+        let mut end = generated
+            .len()
+            .saturating_sub(max_stop_len.saturating_sub(1));
+        while !generated.is_char_boundary(end) {
+            end -= 1;
+        }
+        end
+    }
+
+    /// the length to truncate the generated text to if it is complete, None to keep generating
+    /// the stop strings are looked for in the last `n_new` bytes, and across the boundary with the text before
+    pub fn find_stop(&self, generated: &str, n_new: usize) -> Option<usize> {
+        let max_stop_len = self.stop.iter().map(String::len).max().unwrap_or_default();
+        let mut tail_start = generated
+            .len()
+            .saturating_sub(n_new + max_stop_len.saturating_sub(1));
+        while !generated.is_char_boundary(tail_start) {
+            tail_start -= 1;
+        }
+        let stop_string = self
+            .stop
+            .iter()
+            .filter_map(|stop| generated[tail_start..].find(stop.as_str()))
+            .min()
+            .map(|i| tail_start + i);
+
+        let answer_line = if self.after_answer {
+            answer_line_end(generated)
+        } else {
+            None
+        };
+
+        match (stop_string, answer_line) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// the end of the first non-empty line after `<|pipe|>`, None until that line is complete
+fn answer_line_end(generated: &str) -> Option<usize> {
+    let (_, after_pipe) = generated.split_once("<|pipe|>")?;
+    let answer = after_pipe.trim_start();
+    let answer_start = generated.len() - answer.len();
+    answer.find('\n').map(|end| answer_start + end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prepare_prompt;
+
+    #[test]
+    fn test_stop_criteria() {
+        let params = SamplingParams {
+            stop: vec!["###".to_string()],
+            ..Default::default()
+        };
+        let exqa = prepare_prompt("Paris.", &TaskType::ExtractiveQuestionAnswering);
+        let summarization = prepare_prompt("Paris.", &TaskType::Summarization);
+
+        // the stop string arrives across two tokens
+        let criteria = StopCriteria::new(&summarization, &params);
+        assert_eq!(criteria.find_stop("A summary #", 2), None);
+        assert_eq!(criteria.find_stop("A summary ##", 1), None);
+        assert_eq!(criteria.find_stop("A summary ###", 1), Some(10));
+        assert_eq!(criteria.settled_len("A summary ##"), 10);
+        // only summaries are long, no answer line
+        assert_eq!(
+            criteria.find_stop("{{context}}\nSummarize\n<|pipe|>\nA first line\n", 1),
+            None
+        );
+
+        let criteria = StopCriteria::new(&exqa, &params);
+        assert_eq!(
+            criteria.find_stop("{{context}}\nWhere?\n<|pipe|>\n", 1),
+            None
+        );
+        assert_eq!(
+            criteria.find_stop("{{context}}\nWhere?\n<|pipe|>\nParis", 5),
+            None
+        );
+        assert_eq!(
+            criteria.find_stop("{{context}}\nWhere?\n<|pipe|>\nParis\nand", 4),
+            Some(33)
+        );
+
+        let criteria = StopCriteria::new(
+            &exqa,
+            &SamplingParams {
+                stop_after_answer: Some(false),
+                ..Default::default()
+            },
+        );
+        assert!(criteria.is_empty());
+        assert_eq!(criteria.find_stop("<|pipe|>\nParis\nand", 4), None);
+
+        // the tail starts in the middle of a multi-byte char
+        let criteria = StopCriteria::new(&summarization, &params);
+        assert_eq!(criteria.find_stop("é##", 2), None);
+        assert_eq!(criteria.find_stop("é###", 1), Some(2));
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::mpsc::{channel, Receiver};
+use std::thread::JoinHandle;
+
+use crate::generator::{GenerateError, Generator};
+use crate::sampling::SamplingParams;
+use crate::{ParsedTask, TaskType};
+
+/// an event of `TaskStream`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// a piece of the generated text
+    Delta(String),
+    /// the parsed task once the generation is done, always the last event
+    Done(Box<Result<ParsedTask, GenerateError>>),
+}
+
+/// the events of the generation of a task by `stream_task`, the deltas as they are generated then the parsed task
+pub struct TaskStream {
+    events: Receiver<StreamEvent>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Iterator for TaskStream {
+    type Item = StreamEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.events.recv() {
+            Ok(event) => Some(event),
+            // the worker is done and the events are drained
+            Err(_) => {
+                if let Some(worker) = self.worker.take() {
+                    let _ = worker.join();
+                }
+                None
+            }
+        }
+    }
+}
+
+/// generates a task of `task_type` from the context on a thread owning the generator and streams the generated text,
+/// see `Generator::generate_task_streaming` to stream with a generator borrowing its model, e.g. a llama `Session`
+pub fn stream_task<G>(
+    mut generator: G,
+    context: &str,
+    task_type: &TaskType,
+    params: &SamplingParams,
+) -> TaskStream
+where
+    G: Generator + Send + 'static,
+{
+    let (sender, events) = channel();
+    let context = context.to_string();
+    let task_type = *task_type;
+    let params = params.clone();
+    let worker = std::thread::spawn(move || {
+        let task = generator.generate_task_streaming(&context, &task_type, &params, &mut |text| {
+            // the stream may have been dropped, the generation runs to its end anyway
+            let _ = sender.send(StreamEvent::Delta(text.to_string()));
+        });
+        let _ = sender.send(StreamEvent::Done(Box::new(task)));
+    });
+    TaskStream {
+        events,
+        worker: Some(worker),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::MockGenerator;
+
+    #[test]
+    fn test_stream_task() {
+        let generator = MockGenerator::new(["{{context}}\nWhere?\n<|pipe|>\nParis\nQ: and Rome?"]);
+        let events: Vec<StreamEvent> = stream_task(
+            generator,
+            "Paris is the capital of France.",
+            &TaskType::ExtractiveQuestionAnswering,
+            &SamplingParams::default(),
+        )
+        .collect();
+
+        let (last, deltas) = events.split_last().unwrap();
+        // one delta per token of the mock
+        assert!(deltas.len() > 10);
+        let streamed: String = deltas
+            .iter()
+            .map(|event| match event {
+                StreamEvent::Delta(text) => text.as_str(),
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(streamed, "{{context}}\nWhere?\n<|pipe|>\nParis");
+        match last {
+            StreamEvent::Done(task) => match task.as_ref() {
+                Ok(task) => assert_eq!(task.completion.response, "Paris"),
+                Err(err) => panic!("unexpected error {}", err),
+            },
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+/// assembles the bytes of the generated tokens into text, a token can end in the middle of a multi-byte
+/// character (CJK, emoji, accented letters...) which is emitted once the token with its last byte arrives
+#[derive(Debug, Clone, Default)]
+pub struct Utf8Decoder {
+    /// the first bytes of an incomplete character
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// decodes the bytes of the next token, returns the text of the characters completed by them,
+    /// the invalid bytes become U+FFFD
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+
+        let mut text = String::new();
+        let mut start = 0;
+        loop {
+            match std::str::from_utf8(&self.pending[start..]) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    start = self.pending.len();
+                    break;
+                }
+                Err(err) => {
+                    let valid_end = start + err.valid_up_to();
+                    text.push_str(
+                        std::str::from_utf8(&self.pending[start..valid_end])
+                            .expect("the bytes up to valid_up_to are valid"),
+                    );
+                    match err.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            start = valid_end + len;
+                        }
+                        // an incomplete character at the end, kept for the next token
+                        None => {
+                            start = valid_end;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending.drain(..start);
+        text
+    }
+
+    /// the text of an incomplete character left at the end of the generation, as U+FFFD
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_decoder() {
+        let mut decoder = Utf8Decoder::new();
+        // "é" then "🦀" split byte by byte
+        assert_eq!(decoder.push(b"caf\xc3"), "caf");
+        assert_eq!(decoder.push(b"\xa9 \xf0\x9f"), "é ");
+        assert_eq!(decoder.push(b"\xa6"), "");
+        assert_eq!(decoder.push(b"\x80!"), "🦀!");
+
+        // an invalid byte doesn't stop the decoding
+        assert_eq!(decoder.push(b"a\xffb"), "a\u{FFFD}b");
+
+        assert_eq!(decoder.push(&"東".as_bytes()[..2]), "");
+        assert_eq!(decoder.finish(), "\u{FFFD}");
+        assert_eq!(decoder.push(b"ok"), "ok");
+    }
+}